@@ -0,0 +1,183 @@
+use std::io::Write;
+use sha2::{Digest, Sha256};
+use crate::distr::{Distr, KeyType, ProbType};
+use crate::error::Error;
+
+/// the secret that the rolls of a verifiable session are drawn from
+pub type Seed = [u8; 32];
+
+/// a verifiable session, whose seed stays secret until it is revealed
+/// # Fields
+/// * `path` - the audit log that the commitment and every roll are appended to
+/// * `rolls` - the number of rolls made so far in the session
+#[derive(Clone)]
+pub struct Audit {
+    pub path: String,
+    pub seed: Seed,
+    pub rolls: usize,
+}
+// the seed must stay secret until it is revealed, so only its commitment is ever shown
+impl std::fmt::Debug for Audit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Audit")
+            .field("path", &self.path)
+            .field("commitment", &commitment(&self.seed))
+            .field("rolls", &self.rolls)
+            .finish()
+    }
+}
+
+/// the outcomes drawn in a row from one distribution
+pub type Draws = (Distr, Vec<KeyType>);
+
+/// one roll recorded in an audit log, along with everything it drew
+/// the draws can be replayed from the seed alone, whatever names the roll used
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    Roll { expr: String, total: KeyType, draws: Vec<Draws> },
+    /// a roll that failed part way through, which still used up random numbers
+    Failed { expr: String, draws: Vec<Draws> },
+}
+impl Entry {
+    pub fn expr(&self) -> &str {
+        match self {
+            Entry::Roll { expr, .. } | Entry::Failed { expr, .. } => expr,
+        }
+    }
+    pub fn draws(&self) -> &[Draws] {
+        match self {
+            Entry::Roll { draws, .. } | Entry::Failed { draws, .. } => draws,
+        }
+    }
+}
+
+/// everything logged between one commitment and its reveal
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub commitment: String,
+    pub entries: Vec<Entry>,
+    pub seed: Option<Seed>,
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn seed_from_hex(hex: &str) -> Result<Seed, Error> {
+    let mut seed = [0u8; 32];
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(fail!("a seed must be 64 hex digits, found {:?}", hex));
+    }
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| fail!("a seed must be 64 hex digits, found {:?}", hex))?;
+    }
+    Ok(seed)
+}
+
+/// the published hash of a seed, which can be checked once the seed is revealed
+pub fn commitment(seed: &Seed) -> String {
+    to_hex(&Sha256::digest(seed))
+}
+
+/// write out draws as `{1: 0.5, 2: 0.5}: [2, 1]; {0: 0.25, 1: 0.75}: [1]`
+/// the probabilities are written in full, so that the replay draws from exactly the same distributions
+pub fn format_draws(draws: &[Draws]) -> String {
+    draws.iter()
+        .map(|(distr, outcomes)| {
+            let outcomes = outcomes.iter().map(KeyType::to_string).collect::<Vec<String>>();
+            format!("{}: [{}]", distr, outcomes.join(", "))
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// undo `format_draws`
+fn parse_draws(field: &str) -> Option<Vec<Draws>> {
+    if field.is_empty() {
+        return Some(vec![]);
+    }
+    field.split("; ")
+        .map(|group| {
+            let (distr, outcomes) = group.strip_suffix(']')?.rsplit_once("}: [")?;
+            let pairs = distr.strip_prefix('{')?
+                .split(", ")
+                .map(|pair| {
+                    let (x, p) = pair.split_once(": ")?;
+                    Some((x.parse::<KeyType>().ok()?, p.parse::<ProbType>().ok()?))
+                })
+                .collect::<Option<Vec<(KeyType, ProbType)>>>()?;
+            let outcomes = outcomes.split(", ")
+                .filter(|outcome| !outcome.is_empty())
+                .map(|outcome| outcome.parse::<KeyType>().ok())
+                .collect::<Option<Vec<KeyType>>>()?;
+            Some((Distr::from_probs(&pairs).ok()?, outcomes))
+        })
+        .collect()
+}
+
+/// escape the characters that would split a field or a line, so a multi-line roll stays one entry
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+/// undo `escape`
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match (c, chars.clone().next()) {
+            ('\\', Some(escaped)) => {
+                chars.next();
+                match escaped {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    other => other,
+                }
+            }
+            (c, _) => c,
+        });
+    }
+    unescaped
+}
+
+/// add one tab separated line to the audit log, creating it if needed
+/// the file is only ever appended to, so earlier entries are never rewritten
+pub fn append(path: &str, fields: &[&str]) -> Result<(), Error> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let fields = fields.iter().map(|field| escape(field)).collect::<Vec<String>>();
+    let line = format!("{}\t{}\t{}\n", fields[0], timestamp, fields[1..].join("\t"));
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|err| fail!("could not append to {}: {}", path, err))
+}
+
+/// split an audit log back into its sessions
+pub fn parse_log(src: &str) -> Result<Vec<Session>, Error> {
+    let mut sessions: Vec<Session> = vec![];
+    for (i, line) in src.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let open_session = sessions.last_mut().filter(|session| session.seed.is_none());
+        match (fields.as_slice(), open_session) {
+            (["commit", _, hash], _) => sessions.push(Session { commitment: hash.to_string(), entries: vec![], seed: None }),
+            (["roll", _, _, expr, total, _, draws], Some(session)) => {
+                let total = total.parse::<KeyType>().map_err(|_| fail!("line {}: invalid total {:?}", i + 1, total))?;
+                let draws = parse_draws(draws).ok_or_else(|| fail!("line {}: invalid draws {:?}", i + 1, draws))?;
+                session.entries.push(Entry::Roll { expr: unescape(expr), total, draws });
+            }
+            (["failed", _, _, expr, draws], Some(session)) => {
+                let draws = parse_draws(draws).ok_or_else(|| fail!("line {}: invalid draws {:?}", i + 1, draws))?;
+                session.entries.push(Entry::Failed { expr: unescape(expr), draws });
+            }
+            (["reveal", _, seed], Some(session)) => session.seed = Some(seed_from_hex(seed)?),
+            _ => return Err(fail!("line {}: unexpected audit entry {:?}", i + 1, line)),
+        }
+    }
+    Ok(sessions)
+}
@@ -43,6 +43,18 @@ impl FnType {
     pub fn curry(&self, num: usize) -> FnType {
         FnType { in_types: self.in_types[num..].to_vec(), out_type: self.out_type.clone() }
     }
+    /// the same function taking only its first `num` inputs, and returning a function of the rest
+    /// ```
+    /// let original = fn_type!(Type::Num, Type::Num, -> Type::Distr);
+    /// let split = original.split_at(1);
+    /// assert_eq!(fn_type!(Type::Num, -> Type::Fn(fn_type!(Type::Num, -> Type::Distr))), split)
+    /// ```
+    pub fn split_at(&self, num: usize) -> FnType {
+        if num >= self.in_types.len() {
+            return self.clone();
+        }
+        FnType { in_types: self.in_types[..num].to_vec(), out_type: Box::new(self.curry(num).into()) }
+    }
 }
 
 /// # Fields
@@ -75,7 +87,7 @@ pub struct FnVal {
 }
 impl FnVal {
     pub fn repr(&self) -> String { format!("{:?}", self) }
-    pub fn apply<'s>(&'s self, args: &[Symbol], env: &mut Env) -> Result<Symbol, Error> {
+    pub fn apply(&self, args: &[Symbol], env: &mut Env) -> Result<Symbol, Error> {
         let mut new_exprs: Vec<Symbol> = vec![];
         new_exprs.extend_from_slice(self.exprs.as_slice()); // these were applied previously
         new_exprs.extend_from_slice(args); // we are applying those now
@@ -94,7 +106,7 @@ impl FnVal {
             Ordering::Greater => {
                 // we went to far: let's complain >:(
                 // todo make this error more helpful
-                return Err(fail!("function {} was applied too many arguments (expected {} more, was given {})", self.repr(), self.type_.in_types.len(),  args.len()));
+                Err(fail!("function {} was applied too many arguments (expected {} more, was given {})", self.repr(), self.type_.in_types.len(),  args.len()))
             },
         }
     }
@@ -1,10 +1,13 @@
 use std::collections::BTreeMap;
 use crate::error::Error;
+use crate::render;
 
 pub type KeyType = i32;
 pub type ProbType = f64;
 
-#[derive(Debug, Clone)]
+/// serialized as a map from each outcome to its probability, i.e. `{"1": 0.25, "2": 0.75}`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Distr {
     density_map: BTreeMap<KeyType, f64>
 }
@@ -20,13 +23,150 @@ impl Distr {
         }
         distr
     }
+    /// build a distribution from outcome/probability pairs, such as the ones written in a `{1: 0.25, 2: 0.75}` literal
+    /// repeated outcomes have their probabilities added together
+    pub fn from_probs(pairs: &[(KeyType, ProbType)]) -> Result<Distr, Error> {
+        let mut distr = Distr::new();
+        for (x, p) in pairs {
+            if !p.is_finite() || *p < 0.0 {
+                return Err(fail!("probability of {} must be a non-negative number, found {}", x, p));
+            }
+            distr.update_prob(*x, *p);
+        }
+        let total: ProbType = distr.density_map.values().sum();
+        if !pairs.is_empty() && (total - 1.0).abs() > 1e-9 {
+            return Err(fail!("probabilities must sum to 1, found {}", total));
+        }
+        Ok(distr)
+    }
+    /// the observed frequency of each outcome in a log of rolls
+    pub fn empirical(rolls: &[KeyType]) -> Result<Distr, Error> {
+        if rolls.is_empty() {
+            return Err(fail!("cannot build a distribution from no rolls"));
+        }
+        let mut distr = Distr::new();
+        let weight = 1.0 / rolls.len() as ProbType;
+        for roll in rolls {
+            distr.update_prob(*roll, weight);
+        }
+        Ok(distr)
+    }
+    /// the weighted mixture of several distributions, i.e. "60% of the time use the first, otherwise the second"
+    /// the weights must be non-negative and sum to 1
+    pub fn mixture(parts: &[(ProbType, &Distr)]) -> Result<Distr, Error> {
+        let mut distr = Distr::new();
+        let mut total = 0.0;
+        for (w, part) in parts {
+            if !w.is_finite() || *w < 0.0 {
+                return Err(fail!("mixture weights must be non-negative, found {}", w));
+            }
+            total += w;
+            for x in part.iter() {
+                distr.update_prob(*x, w * part.prob(*x));
+            }
+        }
+        if (total - 1.0).abs() > 1e-9 {
+            return Err(fail!("mixture weights must sum to 1, found {}", total));
+        }
+        Ok(distr)
+    }
+    /// a die whose faces are each equally likely; repeated faces are weighted accordingly
+    pub fn faces(faces: &[KeyType]) -> Distr {
+        let p = 1.0 / faces.len() as f64;
+        let mut distr = Distr::new();
+        for face in faces {
+            distr.update_prob(*face, p);
+        }
+        distr
+    }
+    /// the Fate/Fudge die, with faces {-1, 0, 1}
+    pub fn fate() -> Distr {
+        Distr::faces(&[-1, 0, 1])
+    }
     pub fn stacked_unifs(k: KeyType, n: KeyType) -> Distr {
-        let mut distr = Distr::unif(n);
-        for _i in 0..(k-1) {
-            distr = distr.combine_op(&Distr::unif(n), |x, y| x + y )
+        Distr::stacked(k, &Distr::unif(n))
+    }
+    /// the sum of k rolls of `die`
+    pub fn stacked(k: KeyType, die: &Distr) -> Distr {
+        let mut distr = Distr::from(0);
+        for _i in 0..k {
+            distr = distr.combine_op(die, |x, y| x + y )
         }
         distr
     }
+    /// roll a random number of `die`, where the number of dice is drawn from `count`
+    /// the result is the mixture of `stacked(k, die)` weighted by the probability of each k
+    pub fn compound(count: &Distr, die: &Distr) -> Result<Distr, Error> {
+        let mut distr = Distr::new();
+        let max_k = match count.iter().max() {
+            Some(max_k) => *max_k,
+            None => return Ok(distr),
+        };
+        if let Some(min_k) = count.iter().min().filter(|k| **k < 0) {
+            return Err(fail!("can not roll a negative number of dice ({})", min_k));
+        }
+        // running total of k dice, starting with zero dice
+        let mut stacked = Distr::from(0);
+        for k in 0..=max_k {
+            let p_k = count.prob(k);
+            if p_k > 0.0 {
+                for x in stacked.iter() {
+                    distr.update_prob(*x, p_k * stacked.prob(*x));
+                }
+            }
+            stacked = stacked.combine_op(die, |x, y| x + y);
+        }
+        Ok(distr)
+    }
+    /// roll `die` k times and total only the highest (or lowest) `keep` of them, i.e. `4d6kh3`
+    pub fn kept(k: KeyType, die: &Distr, keep: usize, highest: bool) -> Distr {
+        // track the distribution of the dice kept so far, in ascending order
+        let mut states: BTreeMap<Vec<KeyType>, ProbType> = BTreeMap::new();
+        states.insert(vec![], 1.0);
+        for _ in 0..k {
+            let mut next = BTreeMap::new();
+            for (state, p) in states.iter() {
+                for x in die.iter() {
+                    let mut kept = state.clone();
+                    let at = kept.partition_point(|y| y < x);
+                    kept.insert(at, *x);
+                    if kept.len() > keep {
+                        if highest { kept.remove(0); } else { kept.pop(); }
+                    }
+                    *next.entry(kept).or_insert(0.0) += p * die.prob(*x);
+                }
+            }
+            states = next;
+        }
+        let mut distr = Distr::new();
+        for (kept, p) in states {
+            distr.update_prob(kept.iter().sum(), p);
+        }
+        distr
+    }
+    /// count the successes from rolling `die` k times, where each roll at or above `threshold` is a success
+    /// rolls at or above `double_at` count as two successes, and rolls at or below `subtract_at` take one away
+    pub fn pool(k: KeyType, die: &Distr, threshold: KeyType, double_at: KeyType, subtract_at: KeyType) -> Distr {
+        Distr::stacked(k, &die.successes(threshold, double_at, subtract_at))
+    }
+    /// the number of successes scored by a single roll of this die (see `Distr::pool`)
+    pub fn successes(&self, threshold: KeyType, double_at: KeyType, subtract_at: KeyType) -> Distr {
+        let mut distr = Distr::new();
+        for x in self.iter() {
+            distr.update_prob(Distr::success_score(*x, threshold, double_at, subtract_at), self.prob(*x));
+        }
+        distr
+    }
+    /// the successes counted for a single roll of `x`
+    pub fn success_score(x: KeyType, threshold: KeyType, double_at: KeyType, subtract_at: KeyType) -> KeyType {
+        if x >= threshold {
+            if x >= double_at { 2 } else { 1 }
+        } else if x <= subtract_at {
+            -1
+        } else {
+            0
+        }
+    }
     pub fn iter(&self) -> impl Iterator<Item = &KeyType> {
         self.density_map.keys()
     }
@@ -49,69 +189,323 @@ impl Distr {
         let m = self.mean();
         self.iter()
             .map(|x| (*x as ProbType - m).powf(2.0) * self.prob(*x))
+            .sum::<ProbType>()
+            .sqrt()
+    }
+    /// the probability of rolling at most x
+    pub fn cdf(&self, x: KeyType) -> ProbType {
+        self.density_map.range(..=x).map(|(_, p)| p).sum()
+    }
+    /// the smallest outcome x such that P(X <= x) is at least `q`
+    pub fn percentile(&self, q: ProbType) -> Option<KeyType> {
+        const EPSILON: ProbType = 1e-12;
+        let mut total = 0.0;
+        for (x, p) in self.density_map.iter() {
+            total += p;
+            if total + EPSILON >= q {
+                return Some(*x);
+            }
+        }
+        self.iter().max().cloned()
+    }
+    /// every outcome that either distribution can produce, in order
+    fn joint_support(&self, other: &Distr) -> Vec<KeyType> {
+        let mut support = self.iter().chain(other.iter()).cloned().collect::<Vec<KeyType>>();
+        support.sort_unstable();
+        support.dedup();
+        support
+    }
+    /// half the total absolute difference in probabilities, between 0 (identical) and 1 (disjoint)
+    pub fn total_variation(&self, other: &Distr) -> ProbType {
+        self.joint_support(other)
+            .into_iter()
+            .map(|x| (self.prob(x) - other.prob(x)).abs())
+            .sum::<ProbType>() / 2.0
+    }
+    /// the Kullback-Leibler divergence of `other` from this distribution, in nats
+    /// infinite when this distribution can produce an outcome that `other` can not
+    pub fn kl_divergence(&self, other: &Distr) -> ProbType {
+        self.iter()
+            .map(|x| (self.prob(*x), other.prob(*x)))
+            .filter(|(p, _)| *p > 0.0)
+            .map(|(p, q)| if q > 0.0 { p * (p / q).ln() } else { ProbType::INFINITY })
+            .sum()
+    }
+    /// the total probability the two distributions share, between 0 (disjoint) and 1 (identical)
+    pub fn overlap(&self, other: &Distr) -> ProbType {
+        self.joint_support(other)
+            .into_iter()
+            .map(|x| self.prob(x).min(other.prob(x)))
             .sum()
     }
+    /// first-order stochastic dominance: this distribution is at least as likely as `other` to beat every threshold,
+    /// and strictly more likely for some threshold
+    pub fn dominates(&self, other: &Distr) -> bool {
+        const EPSILON: ProbType = 1e-12;
+        let mut strictly = false;
+        for x in self.joint_support(other) {
+            let (mine, theirs) = (self.cdf(x), other.cdf(x));
+            if mine > theirs + EPSILON {
+                return false;
+            }
+            strictly |= mine + EPSILON < theirs;
+        }
+        strictly
+    }
     pub fn combine_op<F>(&self, other: &Distr, op: F) -> Distr
       where F: Fn(KeyType, KeyType) -> KeyType {
         let mut distr = Distr::new();
-        for ref x in self.iter() {
-            for ref y in other.iter() {
-                distr.update_prob((op)(**x, **y), self.prob(**x) * other.prob(**y));
+        for x in self.iter() {
+            for y in other.iter() {
+                distr.update_prob((op)(*x, *y), self.prob(*x) * other.prob(*y));
             }
         }
         distr
     }
-    pub fn combine_fallible_op<F>(&self, other: &Distr, op: F) -> Result<Distr, Error>
-        where F: Fn(KeyType, KeyType) -> Result<KeyType, Error> {
+    pub fn combine_fallible_op<F>(&self, other: &Distr, mut op: F) -> Result<Distr, Error>
+        where F: FnMut(KeyType, KeyType) -> Result<KeyType, Error> {
         let mut distr = Distr::new();
-        for ref x in self.iter() {
-            for ref y in other.iter() {
-                distr.update_prob((op)(**x, **y)?, self.prob(**x) * other.prob(**y));
+        for x in self.iter() {
+            for y in other.iter() {
+                distr.update_prob((op)(*x, *y)?, self.prob(*x) * other.prob(*y));
             }
         }
         Ok(distr)
     }
+    /// apply `op` to every outcome, merging the probabilities of outcomes that map to the same value
+    pub fn map_op<F>(&self, op: F) -> Distr
+      where F: Fn(KeyType) -> KeyType {
+        let mut distr = Distr::new();
+        for x in self.iter() {
+            distr.update_prob((op)(*x), self.prob(*x));
+        }
+        distr
+    }
+    pub fn map_fallible_op<F>(&self, mut op: F) -> Result<Distr, Error>
+      where F: FnMut(KeyType) -> Result<KeyType, Error> {
+        let mut distr = Distr::new();
+        for x in self.iter() {
+            distr.update_prob((op)(*x)?, self.prob(*x));
+        }
+        Ok(distr)
+    }
+    /// restrict the distribution to the outcomes satisfying `pred`, renormalizing the remaining probabilities
+    pub fn given<F>(&self, pred: F) -> Result<Distr, Error>
+      where F: Fn(KeyType) -> bool {
+        let mut distr = Distr::new();
+        for x in self.iter().filter(|x| (pred)(**x)) {
+            distr.update_prob(*x, self.prob(*x));
+        }
+        let total: ProbType = distr.density_map.values().sum();
+        if total <= 0.0 {
+            return Err(fail!("can not condition on an event with probability zero"));
+        }
+        for p in distr.density_map.values_mut() {
+            *p /= total;
+        }
+        Ok(distr)
+    }
+    /// outcomes below `lo` are treated as `lo` and outcomes above `hi` are treated as `hi`
+    pub fn clamp(&self, lo: KeyType, hi: KeyType) -> Result<Distr, Error> {
+        if lo > hi {
+            return Err(fail!("can not clamp to an empty range ({} > {})", lo, hi));
+        }
+        Ok(self.map_op(|x| x.clamp(lo, hi)))
+    }
+    /// outcomes below `lo` are treated as `lo`
+    pub fn min_at(&self, lo: KeyType) -> Distr {
+        self.map_op(|x| x.max(lo))
+    }
+    /// outcomes above `hi` are treated as `hi`
+    pub fn max_at(&self, hi: KeyType) -> Distr {
+        self.map_op(|x| x.min(hi))
+    }
+    /// the probabilities that a roll of this distribution is (greater than, equal to, less than) an independent roll of `other`
+    /// computed by sweeping both supports in order, without forming the distribution of the difference
+    pub fn compare(&self, other: &Distr) -> (ProbType, ProbType, ProbType) {
+        let (mut win, mut tie) = (0.0, 0.0);
+        // probability that `other` rolled strictly below the current x
+        let mut below = 0.0;
+        let mut others = other.density_map.iter().peekable();
+        for (x, p) in self.density_map.iter() {
+            while let Some((y, q)) = others.peek() {
+                if *y >= x { break; }
+                below += *q;
+                others.next();
+            }
+            win += p * below;
+            tie += p * other.prob(*x);
+        }
+        (win, tie, (1.0 - win - tie).max(0.0))
+    }
     pub fn stat_view(&self) -> String {
         format!("<Mean: {:.3}, Stdev: {:.3}>", self.mean(), self.stdev())
     }
+    pub fn compare_view(&self, other: &Distr) -> String {
+        let mut s = format!("{:>10} | {:>10} | {:>10} | {:>10}\n", "", "left", "right", "delta");
+        s.push_str(&format!("{:->10}-╋-{:->10}-╋-{:->10}-╋-{:->10}\n", "", "", "", ""));
+        s.push_str(&format!("{:>10} | {:>10.3} | {:>10.3} | {:>+10.3}\n", "mean", self.mean(), other.mean(), other.mean() - self.mean()));
+        s.push_str(&format!("{:>10} | {:>10.3} | {:>10.3} | {:>+10.3}\n", "stdev", self.stdev(), other.stdev(), other.stdev() - self.stdev()));
+        s.push_str(&format!("total variation distance: {:.5}\n", self.total_variation(other)));
+        s.push_str(&format!("KL divergence (left || right): {:.5}\n", self.kl_divergence(other)));
+        s.push_str(&format!("KL divergence (right || left): {:.5}\n", other.kl_divergence(self)));
+        s.push_str(&format!("overlap coefficient: {:.5}\n", self.overlap(other)));
+        let dominance = if self.dominates(other) {
+            "left stochastically dominates right"
+        } else if other.dominates(self) {
+            "right stochastically dominates left"
+        } else {
+            "neither stochastically dominates the other"
+        };
+        s.push_str(dominance);
+        s.push('\n');
+        s
+    }
+    /// one row per parameter value, with the mean, stdev and the requested percentiles (0 to 100) of each distribution
+    pub fn sweep_view(rows: &[(KeyType, Distr)], percentiles: &[KeyType]) -> String {
+        let mut s = format!("{:>6} | {:>8} | {:>8}", "x", "mean", "stdev");
+        for q in percentiles {
+            s.push_str(&format!(" | {:>5}", format!("p{}", q)));
+        }
+        s.push_str(&format!("\n{:->6}-╋-{:->8}-╋-{:->8}", "", "", ""));
+        for _ in percentiles {
+            s.push_str(&format!("-╋-{:->5}", ""));
+        }
+        s.push('\n');
+        for (x, distr) in rows {
+            s.push_str(&format!("{:>6} | {:>8.3} | {:>8.3}", x, distr.mean(), distr.stdev()));
+            for q in percentiles {
+                let value = distr.percentile(*q as ProbType / 100.0).map_or("-".to_string(), |v| v.to_string());
+                s.push_str(&format!(" | {:>5}", value));
+            }
+            s.push('\n');
+        }
+        s
+    }
+    /// the mean of each distribution, with a row for each x and a column for each y
+    pub fn grid_view(xs: &[KeyType], ys: &[KeyType], cells: &[Vec<Distr>]) -> String {
+        let mut s = format!("{:>6}", "x \\ y");
+        for y in ys {
+            s.push_str(&format!(" | {:>8}", y));
+        }
+        s.push_str(&format!("\n{:->6}", ""));
+        for _ in ys {
+            s.push_str(&format!("-╋-{:->8}", ""));
+        }
+        s.push('\n');
+        for (x, row) in xs.iter().zip(cells.iter()) {
+            s.push_str(&format!("{:>6}", x));
+            for distr in row {
+                s.push_str(&format!(" | {:>8.3}", distr.mean()));
+            }
+            s.push('\n');
+        }
+        s
+    }
     pub fn hist_view(&self) -> String {
-        if self.len() == 0 {
-            return "The Never Distribution.".to_string();
-        }
-        let min_x: KeyType = *self.iter().min().unwrap();
-        let max_x: KeyType = *self.iter().max().unwrap();
-//        let min_p = self.iter().map(|x| self.prob(*x)).fold(0./0., f64::min);
-        let max_p = self.iter().map(|x| self.prob(*x)).fold(0./0., f64::max);
-        assert!(0.0 <= max_p && max_p <= 1.0);
+        render::histogram(self, &render::HistOptions::default())
+    }
+    /// draw several distributions on a shared x-axis and probability scale, one glyph per distribution
+    /// `width` is the number of columns available to the whole chart
+    pub fn multi_hist_view(distrs: &[(String, &Distr)], width: usize) -> String {
+        const GLYPHS: [char; 6] = ['X', 'O', '#', '+', '*', '~'];
+        let min_x = distrs.iter().filter_map(|(_, distr)| distr.iter().min()).min();
+        let max_x = distrs.iter().filter_map(|(_, distr)| distr.iter().max()).max();
+        let (min_x, max_x) = match (min_x, max_x) {
+            (Some(min_x), Some(max_x)) => (*min_x, *max_x),
+            _ => return "The Never Distribution.".to_string(),
+        };
+        let max_p = distrs.iter()
+            .flat_map(|(_, distr)| distr.density_map.values())
+            .cloned()
+            .fold(f64::NAN, f64::max);
+        let label_width = format!("{}", min_x).len().max(format!("{}", max_x).len());
+        let bar_len = width.saturating_sub(label_width + 2).max(1);
 
         let mut s = String::new();
-
         for x in min_x..=max_x {
-            let k =  ( self.prob(x) * 50.0 / max_p ) as usize;
-            let bar: String = (0..k).map(|_| 'X').collect();
-            s.push_str(&format!("{:2}: {}\n", x, bar));
+            for (i, (_, distr)) in distrs.iter().enumerate() {
+                let k = (distr.prob(x) * bar_len as f64 / max_p) as usize;
+                let bar: String = (0..k).map(|_| GLYPHS[i % GLYPHS.len()]).collect();
+                let label = if i == 0 { format!("{:>width$}", x, width = label_width) } else { " ".repeat(label_width) };
+                s.push_str(&format!("{}: {}\n", label, bar));
+            }
+        }
+        for (i, (label, distr)) in distrs.iter().enumerate() {
+            s.push_str(&format!("{} = {} <Mean: {:.3}, Stdev: {:.3}>\n", GLYPHS[i % GLYPHS.len()], label, distr.mean(), distr.stdev()));
         }
         s
     }
     pub fn table_view(&self) -> String {
-        let mut s = format!("  x | P(x)\n ---╋-----\n");
+        let mut s = "  x | P(x)\n ---╋-----\n".to_string();
         for x in self.iter().cloned() {
             s.push_str(&format!(" {:2} | {:.5}\n", x, self.prob(x)));
         }
         s
     }
 
+    /// outcome/probability pairs, with a header row, for use in a spreadsheet
+    pub fn csv_view(&self) -> String {
+        let mut s = "outcome,probability\n".to_string();
+        for (x, p) in self.density_map.iter() {
+            s.push_str(&format!("{},{:?}\n", x, p));
+        }
+        s
+    }
+    /// read outcome/probability pairs written by `csv_view` (or a spreadsheet), skipping blank lines and a header row
+    pub fn from_csv(src: &str) -> Result<Distr, Error> {
+        let mut pairs = vec![];
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (i == 0 && line.starts_with(|c: char| c.is_alphabetic())) {
+                continue;
+            }
+            let fields = line.split(',').map(str::trim).collect::<Vec<&str>>();
+            let (x, p) = match fields.as_slice() {
+                [x, p] => (x.parse::<KeyType>(), p.parse::<ProbType>()),
+                _ => return Err(fail!("line {}: expected `outcome,probability`, found {:?}", i + 1, line)),
+            };
+            match (x, p) {
+                (Ok(x), Ok(p)) => pairs.push((x, p)),
+                _ => return Err(fail!("line {}: could not read {:?} as an outcome and a probability", i + 1, line)),
+            }
+        }
+        Distr::from_probs(&pairs)
+    }
+    #[cfg(feature = "serde")]
+    pub fn json_view(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a distribution can always be serialized")
+    }
+    #[cfg(feature = "serde")]
+    pub fn from_json(src: &str) -> Result<Distr, Error> {
+        let distr: Distr = serde_json::from_str(src).map_err(|err| fail!("invalid distribution json: {}", err))?;
+        // run it through the same checks as a literal
+        let pairs = distr.density_map.into_iter().collect::<Vec<(KeyType, ProbType)>>();
+        Distr::from_probs(&pairs)
+    }
     pub fn try_to_num(&self) -> Result<KeyType, Error> {
         if self.len() != 1 {
             return Err(fail!("could not convert distribution {:?} into a number", self));
         }
         Ok(self.iter()
-            .nth(0)
-            .map(|x| *x)
+            .next()
+            .copied()
             .unwrap())
     }
 
 }
+/// the canonical textual form, i.e. `{1: 0.25, 2: 0.5, 3: 0.25}`
+/// probabilities are written with enough digits that parsing it again yields the identical distribution
+impl std::fmt::Display for Distr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let pairs = self.density_map
+            .iter()
+            .map(|(x, p)| format!("{}: {:?}", x, p))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{{{}}}", pairs)
+    }
+}
 impl std::convert::From<KeyType> for Distr {
     fn from(n: KeyType) -> Distr {
         let mut distr = Distr::new();
@@ -2,21 +2,53 @@ use crate::symbols::Symbol;
 use crate::type_info::{Type};
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use crate::closures::{FnType, FnVal};
 use crate::error::Error;
+use crate::sampling::Sampler;
+use crate::rolling::Roller;
 
-#[derive(Debug, Clone)]
-pub struct Env {
+/// the bindings made by one `let` or function call
+/// # Fields
+/// * `opaque` - whether the scopes below this one are hidden from it, as they are from a function body
+#[derive(Debug, Clone, Default)]
+struct Scope {
     var_symbols: HashMap<String, Symbol>,
     var_types: HashMap<String, Type>,
+    opaque: bool,
+}
+
+/// why a scope was opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    /// a `let`, which can still see the bindings around it
+    Let,
+    /// a function body, which only sees its own parameter and the globals
+    Call,
+}
+
+#[derive(Debug, Clone)]
+pub struct Env {
+    /// the globals first, then one scope for each `let` or function call being evaluated, innermost last
+    scopes: Vec<Scope>,
+    /// when set, expensive operations are approximated by sampling rather than computed exactly
+    sampler: Option<Sampler>,
+    /// rolls actual dice for the `roll` command, and keeps the session roll log
+    roller: Roller,
+    /// imported scripts, each in its own namespace, reachable as `name.binding`
+    modules: HashMap<String, Env>,
+    /// the scripts being run or imported, innermost last, to catch scripts that import themselves
+    loading: Vec<PathBuf>,
 }
 impl Env {
     pub fn new() -> Env {
-        Env { var_symbols: HashMap::new(), var_types: HashMap::new() }
+        Env { scopes: vec![Scope::default()], sampler: None, roller: Roller::new(), modules: HashMap::new(), loading: vec![] }
     }
+    /// bind `name` in the innermost scope, which is the globals unless a `let` or function call is being evaluated
     pub fn bind_var(&mut self, name: String, value: Symbol, type_: Type) -> &mut Env {
-        self.var_symbols.insert(name.clone(), value);
-        self.var_types.insert(name, type_);
+        let scope = self.scopes.last_mut().expect("the global scope is never popped");
+        scope.var_symbols.insert(name.clone(), value);
+        scope.var_types.insert(name, type_);
         self
     }
     pub fn bind_fn_var(&mut self, name: String, ptr: fn(Vec<Symbol>, &mut Env) -> Result<Symbol, Error>, type_: FnType) -> &mut Env {
@@ -24,7 +56,99 @@ impl Env {
         self.bind_var(name, value.into(), type_.into())
     }
     pub fn lookup_var(&self, name: &str) -> Option<(&Symbol, &Type)> {
-        self.var_types.get(name)
-            .and_then(|type_| self.var_symbols.get(name).map(|symbol| (symbol, type_)))
+        // look qualified names, i.e. `monsters.goblin`, up in the module's namespace
+        if let Some((module, rest)) = name.split_once('.') {
+            return self.modules.get(module).and_then(|env| env.lookup_var(rest));
+        }
+        self.visible_scopes()
+            .find_map(|scope| scope.var_types.get(name).map(|type_| (scope, type_)))
+            .and_then(|(scope, type_)| scope.var_symbols.get(name).map(|symbol| (symbol, type_)))
+    }
+    /// the scopes that a lookup searches, innermost first, ending with the globals
+    fn visible_scopes(&self) -> impl Iterator<Item = &Scope> {
+        let hidden = self.scopes.iter().rposition(|scope| scope.opaque).unwrap_or(0);
+        self.scopes[hidden..].iter().rev().chain(self.scopes[..hidden.min(1)].iter())
+    }
+    /// the values bound by the visible `let`s and function calls, innermost first, excluding the globals
+    pub fn locals(&self) -> Vec<(&String, &Symbol)> {
+        let hidden = self.scopes.iter().rposition(|scope| scope.opaque).unwrap_or(0);
+        self.scopes[hidden.max(1)..].iter()
+            .rev()
+            .flat_map(|scope| scope.var_symbols.iter())
+            .collect()
+    }
+    /// evaluate `f` with `name` bound in a new scope, which is dropped again afterwards, even if `f` fails
+    pub fn with_scope<T, F>(&mut self, frame: Frame, name: String, value: Symbol, type_: Type, f: F) -> T
+      where F: FnOnce(&mut Env) -> T {
+        self.scopes.push(Scope { opaque: frame == Frame::Call, ..Scope::default() });
+        self.bind_var(name, value, type_);
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+    pub fn sampler_mut(&mut self) -> Option<&mut Sampler> {
+        self.sampler.as_mut()
     }
-}
\ No newline at end of file
+    /// switch to sampling (or back to exact evaluation with `None`), returning the previous mode
+    pub fn set_sampler(&mut self, sampler: Option<Sampler>) -> Option<Sampler> {
+        std::mem::replace(&mut self.sampler, sampler)
+    }
+    pub fn roller(&self) -> &Roller {
+        &self.roller
+    }
+    pub fn roller_mut(&mut self) -> &mut Roller {
+        &mut self.roller
+    }
+    /// a fresh namespace for an imported script, with only the standard builtins bound
+    pub fn new_module(&self) -> Env {
+        let mut module = Env::new();
+        module.import_arithmetic().import_dice();
+        module.loading = self.loading.clone();
+        module
+    }
+    pub fn bind_module(&mut self, name: String, module: Env) -> &mut Env {
+        self.modules.insert(name, module);
+        self
+    }
+    /// point the names that a value from the module at `path` uses back into that module,
+    /// so that a function from `m` sees `m`'s bindings wherever it is called
+    pub fn qualify(&self, path: &str, value: Symbol) -> Symbol {
+        match path.split('.').try_fold(self, |env, name| env.modules.get(name)) {
+            Some(module) => module.global_names().iter().fold(value, |value, name| {
+                value.close_over(name, &Symbol::Ident(format!("{}.{}", path, name)))
+            }),
+            None => value,
+        }
+    }
+    /// the qualified names reachable through the modules, then the names of the globals,
+    /// so that a name is never qualified twice
+    fn global_names(&self) -> Vec<String> {
+        let mut names = self.modules.iter()
+            .flat_map(|(module_name, module)| module.global_names().into_iter().map(move |name| format!("{}.{}", module_name, name)))
+            .collect::<Vec<String>>();
+        names.extend(self.scopes[0].var_symbols.keys().cloned());
+        names
+    }
+    /// a relative path is relative to the script being run, if there is one
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        match self.loading.last().and_then(|script| script.parent()) {
+            Some(dir) if Path::new(path).is_relative() => dir.join(path),
+            _ => PathBuf::from(path),
+        }
+    }
+    /// note that a script has started running, failing if it is already running further up
+    pub fn start_loading(&mut self, script: PathBuf) -> Result<(), Error> {
+        if let Some(i) = self.loading.iter().position(|loading| *loading == script) {
+            let cycle = self.loading[i..].iter()
+                .chain(std::iter::once(&script))
+                .map(|path| path.display().to_string())
+                .collect::<Vec<String>>();
+            return Err(fail!("cyclic import: {}", cycle.join(" -> ")));
+        }
+        self.loading.push(script);
+        Ok(())
+    }
+    pub fn finish_loading(&mut self) {
+        self.loading.pop();
+    }
+}
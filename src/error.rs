@@ -1,3 +1,4 @@
+#[allow(unused_macros)]
 macro_rules! create_err {
     ($reason:expr, $opt_span:expr) => {
         Error {
@@ -45,11 +46,10 @@ pub struct Error {
 impl Error {
     /// underline the span in the source string
     pub fn underline(src: &str, span: (usize, usize)) -> String {
-        use std::iter;
         format!("{}\n{}{}",
                 src,
-                iter::repeat(' ').take(span.0).collect::<String>(),
-                iter::repeat('^').take(span.1-span.0).collect::<String>())
+                " ".repeat(span.0),
+                "^".repeat(span.1-span.0))
     }
     /// Concatenate a general error onto this, lower-level situation
     /// # Example
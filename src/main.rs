@@ -6,6 +6,13 @@ extern crate pest;
 #[macro_use] mod closures;
 mod type_info;
 mod distr;
+mod render;
+mod svg;
+mod stats;
+mod sampling;
+mod rolling;
+mod audit;
+mod script;
 mod env;
 mod std_imports;
 mod parse;
@@ -39,38 +46,94 @@ pub fn parse_analyze_evaluate(line: &str, env: &mut Env) -> Result<Symbol, Error
     println!("environment: {:?}", env);
     let ast: Symbol = parse::parse_line(line, env).concat_err(fail!("parser failed"))?;
     ast.walk(env, 0);
-    let type_ = ast.type_check(&env).concat_err(fail!("type checker failed"))?;
+    let type_ = ast.type_check(env).concat_err(fail!("type checker failed"))?;
     println!("{}", ast.repr());
-    println!("=>{}", type_);
+    println!("=>{}", type_.readable());
     Ok(ast.eval(env).concat_err(fail!("evaluator failed"))?.into_owned())
 }
+/// `--svg <path> <expr>` or `--svg-cdf <path> <expr>`: write a chart of the expression to an svg file and exit
+fn export_svg_from_args(args: &[String], env: &mut Env) -> Option<Result<(), Error>> {
+    let kind = match args.first().map(String::as_str) {
+        Some("--svg") => svg::ChartKind::Bar,
+        Some("--svg-cdf") => svg::ChartKind::Cdf,
+        _ => return None,
+    };
+    if args.len() != 3 {
+        return Some(Err(fail!("usage: {} <path.svg> <expression>", args[0])));
+    }
+    let (path, expr) = (&args[1], &args[2]);
+    Some(parse_analyze_evaluate(expr, env).and_then(|symbol| {
+        let distr = symbol.try_to_distr()?;
+        std::fs::write(path, svg::chart(&[(expr.clone(), distr.as_ref())], expr, kind))
+            .map_err(|err| fail!("could not write {}: {}", path, err))
+    }))
+}
 fn main() {
     println!("opening dnd calculator session");
     use type_info::Type;
+    let a = Type::Var("a".to_string());
     let mut env = Env::new();
     env
         .import_arithmetic()
         .import_dice()
         .bind_fn_var("debug".to_string(), |vec, _| {
-            Ok(println!("{:#?}", vec[0]).into())
-        }, fn_type!(Type::Any, -> Type::Any)
+            println!("{:#?}", vec[0]);
+            Ok(Symbol::Nil)
+        }, fn_type!(a, -> Type::Nil)
         )
         ;
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if let Some(res) = export_svg_from_args(&args, &mut env) {
+        if let Err(err) = res {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // `dnd-calculator party.dnd` runs a script instead of opening a session
+    if let Some(path) = args.first() {
+        match script::run_file(path, &mut env) {
+            Ok(symbol) => println!("{}", symbol.repr()),
+            Err(err) => {
+                println!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     loop {
-        let line = prompt_user("/>  ").unwrap();
-        if line.trim() == "exit" { break; }
-        println!("-------------------------");
-        let res = parse_analyze_evaluate(&line, &mut env);
-        println!("-------------------------");
-        match res {
-            Ok(symbol) => {
-                println!(" {:?}\n{}", symbol, symbol.repr());
+        let mut src = prompt_user("/>  ").unwrap();
+        if src.trim() == "exit" { break; }
+        // keep reading while a bracket is open or the line ends in an operator, until a blank line
+        while script::is_incomplete(&src) {
+            let more = prompt_user("..  ").unwrap();
+            if more.is_empty() { break; }
+            src.push('\n');
+            src.push_str(&more);
+        }
+        for statement in script::split_statements(&src) {
+            let line = statement.src;
+            println!("-------------------------");
+            if let Some(sampler) = env.sampler_mut() {
+                sampler.approximated = false;
             }
-            Err(err) => {
-                if let Some(span) = err.opt_span {
-                    println!("{}", Error::underline(&line, span));
+            let res = parse_analyze_evaluate(line, &mut env);
+            println!("-------------------------");
+            match res {
+                Ok(symbol) => {
+                    println!(" {:?}\n{}", symbol, symbol.repr());
+                    if let (Some(sampler), Symbol::Distr(distr)) = (env.sampler_mut(), &symbol) {
+                        if sampler.approximated {
+                            print!("{}", sampling::estimate_view(distr, sampler.trials));
+                        }
+                    }
+                }
+                Err(err) => {
+                    if let Some(span) = err.opt_span {
+                        println!("{}", Error::underline(line, span));
+                    }
+                    println!("{}", err);
                 }
-                println!("{}", err);
             }
         }
     }
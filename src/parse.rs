@@ -1,10 +1,13 @@
 use pest::Parser;
 use pest::iterators::{Pair, Pairs};
+use pest::error::InputLocation;
 use crate::env::Env;
 use crate::symbols::Symbol;
 use crate::error::Error;
-use crate::distr::{KeyType, Distr};
-use pest::prec_climber::{PrecClimber, Assoc, Operator};
+use crate::distr::{KeyType, ProbType, Distr};
+use crate::type_info::Type;
+use crate::closures::FnType;
+use pest::pratt_parser::{PrattParser, Assoc, Op};
 
 
 #[derive(Parser)]
@@ -13,94 +16,361 @@ struct Grammar;
 
 pub fn parse_line(src: &str, _env: &Env) -> Result<Symbol, Error> {
     match Grammar::parse(Rule::line, src) {
-        Ok(pairs) => Ok(parse_expr(pairs)),
-        Err(pest_err) => Err(fail!("{}", pest_err)),
+        Ok(pairs) => parse_expr(pairs),
+        Err(pest_err) => {
+            let span = match pest_err.location {
+                InputLocation::Pos(pos) => (pos, pos + 1),
+                InputLocation::Span(span) => span,
+            };
+            Err(fail_at!(span, "{}", pest_err))
+        }
     }
 }
 
 
-fn parse_expr(pairs: Pairs<Rule>) -> Symbol {
+fn parse_expr(pairs: Pairs<Rule>) -> Result<Symbol, Error> {
     lazy_static! {
-        static ref CLIMBER: PrecClimber<Rule> = PrecClimber::new(vec![
-            Operator::new(Rule::add, Assoc::Left) | Operator::new(Rule::sub, Assoc::Left),
-            Operator::new(Rule::mul, Assoc::Left) | Operator::new(Rule::div, Assoc::Left),
-        ]);
+        static ref PRATT: PrattParser<Rule> = PrattParser::new()
+            .op(Op::postfix(Rule::sampled))
+            .op(Op::postfix(Rule::condition))
+            .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
+            .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left)
+                | Op::infix(Rule::floor_div, Assoc::Left) | Op::infix(Rule::ceil_div, Assoc::Left)
+                | Op::infix(Rule::modulo, Assoc::Left))
+            // -2^2 is -(2^2), as in ordinary maths
+            .op(Op::prefix(Rule::neg))
+            .op(Op::infix(Rule::pow, Assoc::Right));
     }
-    CLIMBER.climb(pairs, make_symbol, |lhs, op, rhs| {
+    PRATT.map_primary(make_symbol).map_prefix(|op, rhs| {
+        match op.as_rule() {
+            Rule::neg => Ok(negate(rhs?)),
+            _ => unreachable!("encountered unreachable rule: {:?}", op.as_rule()),
+        }
+    }).map_infix(|lhs, op, rhs| {
         let target = match op.as_rule() {
             Rule::add => "add",
             Rule::sub => "sub",
             Rule::mul => "mul",
             Rule::div => "div",
+            Rule::floor_div => "floor-div",
+            Rule::ceil_div => "ceil-div",
+            Rule::modulo => "mod",
+            Rule::pow => "pow",
             _ => unreachable!("encountered unreachable rule: {:?}", op.as_rule()),
         }.to_string().into();
-        Symbol::Apply { target: Box::new(target), args: vec![lhs, rhs] }
-    })
+        Ok(Symbol::Apply { target: Box::new(target), args: vec![lhs?, rhs?] })
+    }).map_postfix(|lhs, op| {
+        if op.as_rule() == Rule::sampled {
+            // approximate the whole expression, by evaluating it lazily under the sampler
+            let mut pairs = op.into_inner();
+            let trials = make_symbol(pairs.next().expect("Rule::sampled missing trials"))?;
+            let seed = match pairs.next() {
+                Some(seed) => make_symbol(seed)?,
+                None => 0.into(),
+            };
+            let thunk = Symbol::Lambda { param: "_".to_string(), param_type: None, body: Box::new(lhs?) };
+            return Ok(Symbol::Apply { target: Box::new("estimate".to_string().into()), args: vec![trials, seed, thunk] });
+        }
+        let mut pairs = op.into_inner();
+        let cmp = pairs.next().expect("Rule::condition missing comparison");
+        let target = match cmp.as_rule() {
+            Rule::ge => "given-at-least",
+            Rule::gt => "given-above",
+            Rule::le => "given-at-most",
+            Rule::lt => "given-below",
+            Rule::eq => "given-equal",
+            Rule::ne => "given-not-equal",
+            _ => unreachable!("encountered unreachable rule: {:?}", cmp.as_rule()),
+        }.to_string().into();
+        let mut bound = pairs.next().expect("Rule::condition missing bound");
+        let negated = bound.as_rule() == Rule::neg;
+        if negated {
+            bound = pairs.next().expect("Rule::condition missing bound");
+        }
+        let rhs = make_symbol(bound)?;
+        let rhs = if negated { negate(rhs) } else { rhs };
+        Ok(Symbol::Apply { target: Box::new(target), args: vec![lhs?, rhs] })
+    }).parse(pairs)
 }
 
-fn parse_as_args(pairs: Pairs<Rule>) -> Vec<Symbol> {
+/// negative literals are folded into numbers, anything else is negated when evaluated
+fn negate(symbol: Symbol) -> Symbol {
+    match symbol {
+        Symbol::Num(n) => Symbol::Num(-n),
+        other => Symbol::Apply { target: Box::new("neg".to_string().into()), args: vec![other] },
+    }
+}
+
+fn parse_as_args(pairs: Pairs<Rule>) -> Result<Vec<Symbol>, Error> {
     pairs
         .map(make_symbol)
         .collect()
 }
-fn make_symbol(pair: Pair<Rule>) -> Symbol {
-    match pair.as_rule() {
-        Rule::num => pair.as_str().parse::<KeyType>().expect("Rule::num failed to parse").into(),
+/// parse the text of a pair into a number, complaining at the pair's location if it does not fit
+fn parse_num<T: std::str::FromStr>(pair: &Pair<Rule>) -> Result<T, Error> {
+    let span = pair.as_span();
+    pair.as_str()
+        .parse::<T>()
+        .map_err(|_| fail_at!((span.start(), span.end()), "could not parse {:?} as a number", pair.as_str()))
+}
+/// split a `Rule::dice` into the number of dice and the faces of each die
+/// every die counts towards a pool, so there is nothing for keep-highest or keep-lowest to drop
+fn make_dice(pair: Pair<Rule>) -> Result<(KeyType, Vec<KeyType>), Error> {
+    let span = pair.as_span();
+    let mut pairs = pair.into_inner();
+    let first = pairs.next().expect("Rule::dice missing die");
+    let (count, die) = if first.as_rule() == Rule::dice_count {
+        (parse_num(&first)?, pairs.next().expect("Rule::dice missing die"))
+    } else {
+        (1, first)
+    };
+    if pairs.next().is_some() {
+        return Err(fail_at!((span.start(), span.end()), "a pool of dice can not keep the highest or lowest: {}", span.as_str()));
+    }
+    Ok((count, make_faces(die)?))
+}
+/// list out the faces of a `Rule::die`, i.e. `d6`, `dF`, or `d{1,1,2,3,5,8}`
+fn make_faces(pair: Pair<Rule>) -> Result<Vec<KeyType>, Error> {
+    let faces = pair.into_inner().next().expect("Rule::die missing faces");
+    match faces.as_rule() {
+        Rule::die_sides => {
+            let n: KeyType = parse_num(&faces)?;
+            Ok((1..=n).collect())
+        }
+        Rule::fate_faces => Ok(vec![-1, 0, 1]),
+        Rule::face_list => faces
+            .into_inner()
+            .map(|face| parse_num(&face))
+            .collect(),
+        _ => unreachable!("encountered unreachable rule: {:?}", faces.as_rule()),
+    }
+}
+/// roll `count` of the `Rule::die`, deferring to the dice builtins so that the dice can be rolled individually
+fn make_dice_call(count: Symbol, die: Pair<Rule>) -> Result<Symbol, Error> {
+    let sides = die.clone().into_inner().next().expect("Rule::die missing faces");
+    let (target, faces) = if sides.as_rule() == Rule::die_sides {
+        ("make-dice", parse_num::<KeyType>(&sides)?.into())
+    } else {
+        ("make-custom-dice", Symbol::Seq(make_faces(die)?.into_iter().map(Symbol::from).collect()))
+    };
+    Ok(Symbol::Apply {
+        target: Box::new(target.to_string().into()),
+        args: vec![count, faces],
+    })
+}
+fn make_symbol(pair: Pair<Rule>) -> Result<Symbol, Error> {
+    Ok(match pair.as_rule() {
+        Rule::num => parse_num::<KeyType>(&pair)?.into(),
+        Rule::prob_lit => parse_num::<ProbType>(&pair)?.into(),
         Rule::dice => {
-            let items = pair
-                .as_str()
-                .split('d')
-                .filter(|s| !s.is_empty())
-                .map(|s| s.parse::<KeyType>().expect("Rule::dice failed to parse"))
-                .collect::<Vec<KeyType>>();
-            if items.len() == 1 {
-                Distr::unif(items[0])
+            let mut pairs = pair.into_inner();
+            let mut next = pairs.next().expect("Rule::dice missing die");
+            let count = if next.as_rule() == Rule::dice_count {
+                let count = parse_num::<KeyType>(&next)?;
+                next = pairs.next().expect("Rule::dice missing die");
+                count
             } else {
-                Distr::stacked_unifs(items[0], items[1])
-            }.into()
+                1
+            };
+            match pairs.next() {
+                None => make_dice_call(count.into(), next)?,
+                Some(keep) => {
+                    let target = if keep.as_rule() == Rule::keep_high { "keep-highest" } else { "keep-lowest" };
+                    let keep_count = parse_num::<KeyType>(&keep.into_inner().next().expect("keep missing count"))?;
+                    Symbol::Apply {
+                        target: Box::new(target.to_string().into()),
+                        args: vec![count.into(), Distr::faces(&make_faces(next)?).into(), keep_count.into()],
+                    }
+                }
+            }
+        }
+        Rule::pool_dice => {
+            let span = pair.as_span();
+            let mut pairs = pair.into_inner();
+            let (count, faces) = make_dice(pairs.next().expect("Rule::pool_dice missing dice"))?;
+            let threshold = make_symbol(pairs.next().expect("Rule::pool_dice missing threshold"))?;
+            let (min, max) = match (faces.iter().min(), faces.iter().max()) {
+                (Some(min), Some(max)) => (*min, *max),
+                _ => return Err(fail_at!((span.start(), span.end()), "a die needs at least one face: {}", span.as_str())),
+            };
+            // by default, nothing is doubled and nothing is subtracted
+            let mut double_at: Symbol = (max + 1).into();
+            let mut subtract_at: Symbol = (min - 1).into();
+            for modifier in pairs {
+                let rule = modifier.as_rule();
+                let value = make_symbol(modifier.into_inner().next().expect("pool modifier missing value"))?;
+                match rule {
+                    Rule::pool_double => double_at = value,
+                    Rule::pool_subtract => subtract_at = value,
+                    _ => unreachable!("encountered unreachable rule: {:?}", rule),
+                }
+            }
+            Symbol::Apply {
+                target: Box::new("pool-with".to_string().into()),
+                args: vec![count.into(), Distr::faces(&faces).into(), threshold, double_at, subtract_at],
+            }
+        }
+        Rule::compound_dice => {
+            let mut pairs = pair.into_inner();
+            let count = make_symbol(pairs.next().expect("Rule::compound_dice missing count"))?;
+            let die = pairs
+                .next()
+                .and_then(|compound_die| compound_die.into_inner().next())
+                .expect("Rule::compound_dice missing die");
+            make_dice_call(count, die)?
+        }
+        Rule::distr_lit => {
+            let span = pair.as_span();
+            let pairs = pair
+                .into_inner()
+                .map(|outcome_prob| {
+                    let mut pairs = outcome_prob.into_inner();
+                    let outcome = parse_num::<KeyType>(&pairs.next().expect("Rule::outcome_prob missing outcome"))?;
+                    let prob = parse_num::<ProbType>(&pairs.next().expect("Rule::outcome_prob missing prob"))?;
+                    Ok((outcome, prob))
+                })
+                .collect::<Result<Vec<(KeyType, ProbType)>, Error>>()?;
+            Distr::from_probs(&pairs)
+                .map_err(|err| err.concat(fail_at!((span.start(), span.end()), "invalid distribution literal")))?
+                .into()
+        }
+        Rule::string => {
+            let inner = pair.into_inner().next().expect("Rule::string missing contents");
+            let mut text = String::new();
+            let mut chars = inner.as_str().chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(escaped) => text.push(escaped),
+                        None => {}
+                    },
+                    _ => text.push(c),
+                }
+            }
+            Symbol::Str(text)
         }
-        Rule::ident => pair.as_str().to_string().into(),
-        Rule::expr => parse_expr(pair.into_inner()),
+        Rule::ident | Rule::qualified_ident => pair.as_str().to_string().into(),
+        Rule::expr => parse_expr(pair.into_inner())?,
         Rule::range_to => Symbol::Apply{
             target: Box::new("range-to".to_string().into()),
-            args: parse_as_args(pair.into_inner())
+            args: parse_as_args(pair.into_inner())?
         },
         Rule::repeats => Symbol::Apply{
             target: Box::new("repeat".to_string().into()),
-            args: parse_as_args(pair.into_inner())
+            args: parse_as_args(pair.into_inner())?
         },
-        Rule::fn_lit => unreachable!("fn literal not supported"),
-        Rule::seq => Symbol::Seq(pair.into_inner()
-                                     .map(make_symbol)
-                                     .collect()),
+        Rule::fn_lit => {
+            let mut pairs = pair.into_inner();
+            Symbol::Lambda {
+                param: pairs.next().expect("Rule::fn_lit missing param").as_str().to_string(),
+                param_type: make_annotation(&mut pairs)?,
+                body: Box::new(make_symbol(pairs.next().expect("Rule::fn_lit missing body"))?),
+            }
+        }
+        Rule::let_in => {
+            let mut pairs = pair.into_inner();
+            Symbol::Let {
+                name: pairs.next().expect("Rule::let_in missing name").as_str().to_string(),
+                def_type: make_annotation(&mut pairs)?,
+                value: Box::new(make_symbol(pairs.next().expect("Rule::let_in missing value"))?),
+                body: Box::new(make_symbol(pairs.next().expect("Rule::let_in missing body"))?),
+            }
+        }
+        Rule::seq => Symbol::Seq(parse_as_args(pair.into_inner())?),
         Rule::fn_call => {
             let mut pairs = pair.into_inner();
-            let target = pairs
-                .next()
-                .expect("fn_call needs target string")
-                .as_str()
-                .to_string()
-                .into();
-            Symbol::Apply { target: Box::new(target), args: parse_as_args(pairs) }
+            let target = make_symbol(pairs.next().expect("fn_call needs target"))?;
+            Symbol::Apply { target: Box::new(target), args: parse_as_args(pairs)? }
         },
+        Rule::roll => {
+            let expr = pair.into_inner().next().expect("Rule::roll missing expr");
+            Symbol::Apply {
+                target: Box::new("roll".to_string().into()),
+                args: vec![
+                    Symbol::Str(expr.as_str().trim().to_string()),
+                    Symbol::Lambda { param: "_".to_string(), param_type: None, body: Box::new(make_symbol(expr)?) },
+                ],
+            }
+        }
+        Rule::run => {
+            let path = pair.into_inner().next().expect("Rule::run missing path");
+            let path = match path.as_rule() {
+                Rule::script_path => Symbol::Str(path.as_str().to_string()),
+                _ => make_symbol(path)?,
+            };
+            Symbol::Apply { target: Box::new("run".to_string().into()), args: vec![path] }
+        }
+        Rule::import => {
+            let mut pairs = pair.into_inner();
+            let path = make_symbol(pairs.next().expect("Rule::import missing path"))?;
+            // without `as`, the namespace is named after the file, i.e. `monsters` for "lib/monsters.dnd"
+            let name = match pairs.next() {
+                Some(name) => name.as_str().to_string(),
+                None => {
+                    let path = path.try_to_str()?;
+                    std::path::Path::new(path)
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .map(str::to_string)
+                        .ok_or_else(|| fail!("can not name a namespace after {:?}, use `import {:?} as name`", path, path))?
+                }
+            };
+            Symbol::Apply { target: Box::new("import".to_string().into()), args: vec![path, Symbol::Str(name)] }
+        }
         Rule::assignment => {
             let mut pairs = pair.into_inner();
             Symbol::Assigner {
                 name: pairs.next().expect("Rule::assignment missing name").as_str().to_string(),
                 def_type: None,
-                expr: Box::new(make_symbol(pairs.next().expect("Rule::assignment missing expr"))),
+                expr: Box::new(make_symbol(pairs.next().expect("Rule::assignment missing expr"))?),
             }
         }
         Rule::assignment_with_type => {
             let mut pairs = pair.into_inner();
             Symbol::Assigner {
                 name: pairs.next().expect("Rule::assignment missing name").as_str().to_string(),
-                def_type: Some(pairs.next().expect("Rule::assignment_with_type missing type").as_str().to_string()),
-                expr: Box::new(make_symbol(pairs.next().expect("Rule::assignment missing expr"))),
+                def_type: Some(make_type(pairs.next().expect("Rule::assignment_with_type missing type"))?),
+                expr: Box::new(make_symbol(pairs.next().expect("Rule::assignment missing expr"))?),
             }
         }
-                Rule::add | Rule::sub | Rule::mul  | Rule::div
-           | Rule::parens | Rule::term | Rule::op | Rule::eoi | Rule::line
+                Rule::add | Rule::sub | Rule::mul  | Rule::div | Rule::floor_div | Rule::ceil_div | Rule::modulo | Rule::pow | Rule::neg | Rule::signed_term | Rule::dice_count | Rule::die | Rule::compound_die
+           | Rule::die_sides | Rule::fate_faces | Rule::face_list | Rule::face | Rule::script_path | Rule::keep | Rule::keep_high | Rule::keep_low | Rule::keep_count
+           | Rule::pool_double | Rule::pool_subtract | Rule::outcome_prob | Rule::outcome | Rule::prob
+           | Rule::keyword | Rule::string_inner | Rule::condition | Rule::sampled | Rule::cmp | Rule::ge | Rule::gt | Rule::le | Rule::lt | Rule::eq | Rule::ne
+           | Rule::type_expr | Rule::fn_type_expr | Rule::seq_type | Rule::base_type
+           | Rule::parens | Rule::atom | Rule::term | Rule::op | Rule::eoi | Rule::line
            | Rule::WHITESPACE | Rule::COMMENT => unreachable!("reached unreachable rule: {:?}", pair.as_rule()),
+    })
+}
+
+/// the type written in an annotation, i.e. `Seq<Num>` or `Fn(Distr, Num) -> Distr`
+fn make_type(pair: Pair<Rule>) -> Result<Type, Error> {
+    Ok(match pair.as_rule() {
+        Rule::type_expr => make_type(pair.into_inner().next().expect("Rule::type_expr missing type"))?,
+        Rule::seq_type => Type::Seq(Box::new(make_type(pair.into_inner().next().expect("Rule::seq_type missing item type"))?)),
+        Rule::fn_type_expr => {
+            let mut in_types = pair.into_inner().map(make_type).collect::<Result<Vec<Type>, Error>>()?;
+            let out_type = in_types.pop().expect("Rule::fn_type_expr missing output type");
+            FnType { in_types, out_type: Box::new(out_type) }.into()
+        }
+        Rule::base_type => {
+            let span = pair.as_span();
+            match pair.as_str() {
+                // lower case names are type variables, i.e. the `a` in `Fn(a) -> a`
+                name if name.starts_with(|c: char| c.is_ascii_lowercase()) => Type::Var(name.to_string()),
+                name => Type::try_from(name).ok_or_else(|| fail_at!((span.start(), span.end()), "invalid type: {}", name))?,
+            }
+        }
+        _ => unreachable!("not a type: {:?}", pair.as_rule()),
+    })
+}
+
+/// the optional `: Type` after a name
+fn make_annotation(pairs: &mut Pairs<Rule>) -> Result<Option<Type>, Error> {
+    match pairs.peek() {
+        Some(pair) if pair.as_rule() == Rule::type_expr => make_type(pairs.next().expect("peeked")).map(Some),
+        _ => Ok(None),
     }
-}
\ No newline at end of file
+}
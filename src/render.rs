@@ -0,0 +1,162 @@
+use crate::distr::{Distr, KeyType, ProbType};
+
+/// horizontal bars grow to the right, vertical bars grow upwards
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+/// a log scale keeps unlikely tail events visible next to the likely ones
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scale {
+    Linear,
+    Log,
+}
+
+/// # Fields
+/// * `width` - the number of columns available to the whole chart
+/// * `height` - the number of rows used by a vertical chart
+/// * `max_bins` - outcomes are grouped into bins when there are more of them than this
+#[derive(Clone, Debug)]
+pub struct HistOptions {
+    pub orientation: Orientation,
+    pub scale: Scale,
+    pub width: usize,
+    pub height: usize,
+    pub max_bins: usize,
+}
+impl Default for HistOptions {
+    fn default() -> HistOptions {
+        HistOptions {
+            orientation: Orientation::Horizontal,
+            scale: Scale::Linear,
+            width: terminal_width(),
+            height: 16,
+            max_bins: 60,
+        }
+    }
+}
+
+/// the width of the attached terminal, or 80 columns if there is none
+pub fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(terminal_size::Width(w), _)| w as usize)
+        .unwrap_or(80)
+}
+
+/// partial blocks, from one eighth to a full block
+const HORIZONTAL_EIGHTHS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+const VERTICAL_EIGHTHS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// a run of consecutive outcomes drawn as a single bar
+struct Bin {
+    lo: KeyType,
+    hi: KeyType,
+    p: ProbType,
+}
+impl Bin {
+    fn label(&self) -> String {
+        if self.lo == self.hi {
+            format!("{}", self.lo)
+        } else {
+            format!("{}..{}", self.lo, self.hi)
+        }
+    }
+}
+
+/// group the support of `distr` into at most `max_bins` bins of equal width
+fn make_bins(distr: &Distr, max_bins: usize) -> Vec<Bin> {
+    let (min_x, max_x) = match (distr.iter().min(), distr.iter().max()) {
+        (Some(min_x), Some(max_x)) => (*min_x, *max_x),
+        _ => return vec![],
+    };
+    let span = (max_x - min_x) as usize + 1;
+    let bin_width = span.div_ceil(max_bins.max(1)).max(1) as KeyType;
+    let mut bins = vec![];
+    let mut lo = min_x;
+    while lo <= max_x {
+        let hi = (lo + bin_width - 1).min(max_x);
+        let p = (lo..=hi).map(|x| distr.prob(x)).sum();
+        bins.push(Bin { lo, hi, p });
+        lo = hi + 1;
+    }
+    bins
+}
+
+/// the fraction of the full bar length that `p` should occupy
+fn bar_fraction(p: ProbType, max_p: ProbType, min_p: ProbType, scale: Scale) -> ProbType {
+    if p <= 0.0 || max_p <= 0.0 {
+        return 0.0;
+    }
+    match scale {
+        Scale::Linear => p / max_p,
+        Scale::Log => {
+            // leave the least likely bin a sliver of a bar, rather than nothing at all
+            let floor = (min_p / 10.0).ln();
+            if max_p.ln() <= floor {
+                return 1.0;
+            }
+            ((p.ln() - floor) / (max_p.ln() - floor)).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// a bar of `len` cells, filled to `fraction` with eighth-block precision
+fn horizontal_bar(fraction: ProbType, len: usize) -> String {
+    let eighths = (fraction * (len * 8) as ProbType).round() as usize;
+    let mut bar = HORIZONTAL_EIGHTHS[7].to_string().repeat(eighths / 8);
+    if !eighths.is_multiple_of(8) {
+        bar.push(HORIZONTAL_EIGHTHS[eighths % 8 - 1]);
+    }
+    bar
+}
+
+pub fn histogram(distr: &Distr, options: &HistOptions) -> String {
+    if distr.len() == 0 {
+        return "The Never Distribution.".to_string();
+    }
+    let max_bins = match options.orientation {
+        Orientation::Horizontal => options.max_bins,
+        // every bin needs a column, after room for the probability axis
+        Orientation::Vertical => options.max_bins.min(options.width.saturating_sub(12).max(1)),
+    };
+    let bins = make_bins(distr, max_bins);
+    let max_p = bins.iter().map(|bin| bin.p).fold(0.0, ProbType::max);
+    let min_p = bins.iter().map(|bin| bin.p).filter(|p| *p > 0.0).fold(1.0, ProbType::min);
+    let fractions = bins.iter()
+        .map(|bin| bar_fraction(bin.p, max_p, min_p, options.scale))
+        .collect::<Vec<ProbType>>();
+    match options.orientation {
+        Orientation::Horizontal => {
+            let label_width = bins.iter().map(|bin| bin.label().len()).max().unwrap_or(0);
+            // leave room for the label, the separator, and the probability at the end of the bar
+            let bar_len = options.width.saturating_sub(label_width + 12).max(1);
+            let mut s = String::new();
+            for (bin, fraction) in bins.iter().zip(fractions.iter()) {
+                s.push_str(&format!("{:>width$} │{} {:.4}\n", bin.label(), horizontal_bar(*fraction, bar_len), bin.p, width = label_width));
+            }
+            s
+        }
+        Orientation::Vertical => {
+            let height = options.height.max(1);
+            let mut s = String::new();
+            for row in (0..height).rev() {
+                let axis = if row + 1 == height { format!("{:.4}", max_p) } else { String::new() };
+                s.push_str(&format!("{:>10} ┤", axis));
+                for fraction in fractions.iter() {
+                    // the number of eighths of this row that the bar fills
+                    let eighths = (fraction * (height * 8) as ProbType).round() as usize;
+                    let filled = eighths.saturating_sub(row * 8).min(8);
+                    s.push(if filled == 0 { ' ' } else { VERTICAL_EIGHTHS[filled - 1] });
+                }
+                s.push('\n');
+            }
+            s.push_str(&format!("{:>10} └{}\n", "", "─".repeat(bins.len())));
+            let first = bins.first().map(Bin::label).unwrap_or_default();
+            let last = bins.last().map(Bin::label).unwrap_or_default();
+            let gap = bins.len().saturating_sub(first.len() + last.len()).max(1);
+            s.push_str(&format!("{:>10}  {}{}{}\n", "", first, " ".repeat(gap), last));
+            s
+        }
+    }
+}
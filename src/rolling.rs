@@ -0,0 +1,206 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use crate::distr::{Distr, KeyType, ProbType};
+use crate::audit::{self, Audit, Draws, Seed};
+use crate::error::Error;
+
+/// the faces shown by one group of dice, i.e. `4d6kh3: [6, 5, (2), 4]`
+/// # Fields
+/// * `label` - the dice that were rolled, as they would be written
+/// * `faces` - every die, in the order it was rolled
+/// * `kept` - whether each die counts towards the total
+#[derive(Clone, Debug)]
+pub struct DieRoll {
+    pub label: String,
+    pub faces: Vec<KeyType>,
+    pub kept: Vec<bool>,
+}
+impl std::fmt::Display for DieRoll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let faces = self.faces.iter()
+            .zip(self.kept.iter())
+            .map(|(face, kept)| if *kept { face.to_string() } else { format!("({})", face) })
+            .collect::<Vec<String>>();
+        write!(f, "{}: [{}]", self.label, faces.join(", "))
+    }
+}
+
+/// one entry in the session roll log
+#[derive(Clone, Debug)]
+pub struct RollRecord {
+    pub expr: String,
+    pub dice: Vec<DieRoll>,
+    pub total: KeyType,
+}
+impl std::fmt::Display for RollRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.expr, self.total)?;
+        for dice in self.dice.iter() {
+            write!(f, "\n    {}", dice)?;
+        }
+        Ok(())
+    }
+}
+
+/// rolls actual dice, keeping a log of every roll in the session
+/// the generator is a fixed algorithm, so that anyone can replay a revealed seed, whatever their platform or version
+/// # Fields
+/// * `current` - the dice rolled so far by the roll in progress, if there is one
+/// * `drawn` - every outcome drawn so far by the roll in progress, which is what a verifiable session replays
+/// * `audit` - the verifiable session in progress, if there is one
+#[derive(Clone, Debug)]
+pub struct Roller {
+    rng: ChaCha20Rng,
+    current: Option<Vec<DieRoll>>,
+    drawn: Vec<Draws>,
+    audit: Option<Audit>,
+    pub history: Vec<RollRecord>,
+}
+impl Roller {
+    pub fn new() -> Roller {
+        Roller { rng: ChaCha20Rng::from_entropy(), current: None, drawn: vec![], audit: None, history: vec![] }
+    }
+    /// a roller that repeats the rolls of a revealed verifiable session
+    pub fn replay(seed: Seed) -> Roller {
+        Roller { rng: ChaCha20Rng::from_seed(seed), current: None, drawn: vec![], audit: None, history: vec![] }
+    }
+    /// restart the random number generator, so that the following rolls can be reproduced
+    pub fn seed(&mut self, seed: u64) -> Result<(), Error> {
+        if let Some(audit) = self.audit.as_ref() {
+            return Err(fail!("can not reseed during the verifiable session logged to {}", audit.path));
+        }
+        self.rng = ChaCha20Rng::seed_from_u64(seed);
+        Ok(())
+    }
+    /// start a verifiable session with a secret seed, logging and returning the commitment to it
+    pub fn commit(&mut self, path: &str) -> Result<String, Error> {
+        if let Some(audit) = self.audit.as_ref() {
+            return Err(fail!("a verifiable session is already being logged to {}", audit.path));
+        }
+        let seed: Seed = ChaCha20Rng::from_entropy().gen();
+        let commitment = audit::commitment(&seed);
+        audit::append(path, &["commit", &commitment])?;
+        self.rng = ChaCha20Rng::from_seed(seed);
+        self.audit = Some(Audit { path: path.to_string(), seed, rolls: 0 });
+        Ok(commitment)
+    }
+    /// end the verifiable session logged to `path`, logging and returning its seed
+    pub fn reveal(&mut self, path: &str) -> Result<String, Error> {
+        let seed = match self.audit.as_ref() {
+            Some(audit) if audit.path == path => audit.seed,
+            Some(audit) => return Err(fail!("the verifiable session is logged to {}, not {}", audit.path, path)),
+            None => return Err(fail!("there is no verifiable session to reveal")),
+        };
+        let hex = audit::to_hex(&seed);
+        audit::append(path, &["reveal", &hex])?;
+        self.audit = None;
+        self.rng = ChaCha20Rng::from_entropy();
+        Ok(hex)
+    }
+    pub fn is_rolling(&self) -> bool {
+        self.current.is_some()
+    }
+    pub fn begin(&mut self) {
+        self.current = Some(vec![]);
+        self.drawn.clear();
+    }
+    /// stop rolling, and add the dice rolled since `begin` to the log
+    pub fn finish(&mut self, expr: &str, total: KeyType) -> Result<&RollRecord, Error> {
+        let dice = self.current.take().unwrap_or_default();
+        let record = RollRecord { expr: expr.to_string(), dice, total };
+        if let Some(audit) = self.audit.as_mut() {
+            audit.rolls += 1;
+            let breakdown = record.dice.iter().map(DieRoll::to_string).collect::<Vec<String>>().join("; ");
+            let draws = audit::format_draws(&std::mem::take(&mut self.drawn));
+            audit::append(&audit.path, &["roll", &audit.rolls.to_string(), expr, &total.to_string(), &breakdown, &draws])?;
+        }
+        self.history.push(record);
+        Ok(self.history.last().expect("roll was just logged"))
+    }
+    /// abandon the roll in progress without adding it to the session log
+    /// a verifiable session still records it, since it used up random numbers
+    pub fn cancel(&mut self, expr: &str) -> Result<(), Error> {
+        self.current = None;
+        if let Some(audit) = self.audit.as_mut() {
+            audit.rolls += 1;
+            let draws = audit::format_draws(&std::mem::take(&mut self.drawn));
+            audit::append(&audit.path, &["failed", &audit.rolls.to_string(), expr, &draws])?;
+        }
+        Ok(())
+    }
+    /// a single outcome of `distr`, or `None` if it has no outcomes
+    pub fn draw(&mut self, distr: &Distr) -> Option<KeyType> {
+        let u = self.rng.gen::<ProbType>();
+        let mut total = 0.0;
+        let drawn = *distr.iter()
+            .find(|x| {
+                total += distr.prob(**x);
+                u < total
+            })
+            .or_else(|| distr.iter().max())?;
+        if self.current.is_some() {
+            match self.drawn.last_mut() {
+                Some((last, outcomes)) if last == distr => outcomes.push(drawn),
+                _ => self.drawn.push((distr.clone(), vec![drawn])),
+            }
+        }
+        Some(drawn)
+    }
+    /// roll `count` of `die`, logging each face, and total the highest (or lowest) `keep` of them
+    pub fn roll_dice(&mut self, count: KeyType, die: &Distr, keep: Option<(usize, bool)>) -> KeyType {
+        self.roll_faces(count, die, keep).iter().sum()
+    }
+    /// roll `count` of `die`, logging each face, and return the faces that are kept
+    pub fn roll_faces(&mut self, count: KeyType, die: &Distr, keep: Option<(usize, bool)>) -> Vec<KeyType> {
+        let faces = (0..count.max(0))
+            .filter_map(|_| self.draw(die))
+            .collect::<Vec<KeyType>>();
+        let mut kept = vec![true; faces.len()];
+        let mut label = format!("{}{}", count, die_label(die));
+        if let Some((keep, highest)) = keep {
+            let mut order = (0..faces.len()).collect::<Vec<usize>>();
+            // later dice are dropped first among ties, so the breakdown reads left to right
+            order.sort_by_key(|i| if highest { -faces[*i] } else { faces[*i] });
+            for i in order.into_iter().skip(keep) {
+                kept[i] = false;
+            }
+            label.push_str(&format!("{}{}", if highest { "kh" } else { "kl" }, keep));
+        }
+        let kept_faces = faces.iter()
+            .zip(kept.iter())
+            .filter(|(_, kept)| **kept)
+            .map(|(face, _)| *face)
+            .collect();
+        if let Some(current) = self.current.as_mut() {
+            current.push(DieRoll { label, faces, kept });
+        }
+        kept_faces
+    }
+    /// a csv of the roll log, with one row for each roll
+    pub fn history_csv(&self) -> String {
+        let mut s = "expression,total,dice\n".to_string();
+        for record in self.history.iter() {
+            let dice = record.dice.iter().map(DieRoll::to_string).collect::<Vec<String>>().join("; ");
+            s.push_str(&format!("\"{}\",{},\"{}\"\n", record.expr.replace('"', "\"\""), record.total, dice));
+        }
+        s
+    }
+}
+
+/// how a die would be written, i.e. `d6`, `dF` or `d{1,1,2}`
+pub fn die_label(die: &Distr) -> String {
+    let faces = die.iter().cloned().collect::<Vec<KeyType>>();
+    let n = faces.len() as KeyType;
+    if faces == (1..=n).collect::<Vec<KeyType>>() && die == &Distr::unif(n) {
+        format!("d{}", n)
+    } else if die == &Distr::fate() {
+        "dF".to_string()
+    } else {
+        // repeat each face as often as its probability implies, i.e. `d{1,1,2}` rather than `d{1,2}`
+        let rarest = faces.iter().map(|x| die.prob(*x)).fold(1.0, ProbType::min);
+        let faces = faces.iter()
+            .flat_map(|x| std::iter::repeat_n(x.to_string(), (die.prob(*x) / rarest).round() as usize))
+            .collect::<Vec<String>>();
+        format!("d{{{}}}", faces.join(","))
+    }
+}
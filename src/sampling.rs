@@ -0,0 +1,82 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use crate::distr::{Distr, KeyType, ProbType};
+use crate::error::Error;
+
+/// draws random outcomes for the approximate evaluation mode
+/// the same seed always produces the same draws, so sampled results are reproducible
+/// on any platform, and with any version of rand, since `StdRng` makes no such promise
+/// # Fields
+/// * `trials` - the number of samples behind every approximated distribution
+/// * `approximated` - whether any result has been sampled rather than computed exactly
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    pub trials: usize,
+    pub seed: u64,
+    pub approximated: bool,
+    rng: ChaCha20Rng,
+}
+impl Sampler {
+    pub fn new(trials: usize, seed: u64) -> Sampler {
+        Sampler { trials, seed, approximated: false, rng: ChaCha20Rng::seed_from_u64(seed) }
+    }
+    /// whether approximating is cheaper than doing about `exact_work` steps of exact convolution
+    pub fn prefers(&self, exact_work: usize) -> bool {
+        exact_work > self.trials
+    }
+    /// `n` independent outcomes of `distr`, by inverting its cdf
+    pub fn draws(&mut self, distr: &Distr, n: usize) -> Vec<KeyType> {
+        let mut total = 0.0;
+        let cumulative = distr.iter()
+            .map(|x| {
+                total += distr.prob(*x);
+                (*x, total)
+            })
+            .collect::<Vec<(KeyType, ProbType)>>();
+        if cumulative.is_empty() {
+            return vec![];
+        }
+        self.approximated = true;
+        (0..n)
+            .map(|_| {
+                let u = self.rng.gen::<ProbType>() * total;
+                let i = cumulative.partition_point(|(_, c)| *c <= u).min(cumulative.len() - 1);
+                cumulative[i].0
+            })
+            .collect()
+    }
+    /// one pair of independent outcomes for each trial
+    pub fn draw_pairs(&mut self, left: &Distr, right: &Distr) -> Vec<(KeyType, KeyType)> {
+        let xs = self.draws(left, self.trials);
+        let ys = self.draws(right, self.trials);
+        xs.into_iter().zip(ys).collect()
+    }
+    /// the approximate counterpart of `Distr::compound`, or of `Distr::stacked` when `count` is a single number
+    pub fn compound(&mut self, count: &Distr, die: &Distr) -> Result<Distr, Error> {
+        if let Some(min_k) = count.iter().min().filter(|k| **k < 0) {
+            return Err(fail!("can not roll a negative number of dice ({})", min_k));
+        }
+        let outcomes = self.draws(count, self.trials)
+            .into_iter()
+            .map(|k| self.draws(die, k as usize).into_iter().sum())
+            .collect::<Vec<KeyType>>();
+        Distr::empirical(&outcomes)
+    }
+}
+
+/// the estimated mean and percentiles of a distribution sampled `trials` times, with 95% confidence intervals
+pub fn estimate_view(distr: &Distr, trials: usize) -> String {
+    const Z: ProbType = 1.96;
+    let n = trials.max(1) as ProbType;
+    let mut s = format!("estimated from {} trials, with 95% confidence intervals\n", trials);
+    let margin = Z * distr.stdev() / n.sqrt();
+    s.push_str(&format!("{:>6} {:>10.3}  [{:.3}, {:.3}]\n", "mean", distr.mean(), distr.mean() - margin, distr.mean() + margin));
+    for q in [5, 25, 50, 75, 95].iter() {
+        let q_frac = *q as ProbType / 100.0;
+        // the number of samples below a percentile is binomial, so its rank is uncertain by about this much
+        let rank_margin = Z * (q_frac * (1.0 - q_frac) / n).sqrt();
+        let show = |q: ProbType| distr.percentile(q.clamp(0.0, 1.0)).map_or("-".to_string(), |v| v.to_string());
+        s.push_str(&format!("{:>6} {:>10}  [{}, {}]\n", format!("p{}", q), show(q_frac), show(q_frac - rank_margin), show(q_frac + rank_margin)));
+    }
+    s
+}
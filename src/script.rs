@@ -0,0 +1,147 @@
+use crate::env::Env;
+use crate::error::Error;
+use crate::symbols::Symbol;
+
+/// one statement of a script, and the byte offset where it starts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement<'a> {
+    pub src: &'a str,
+    pub offset: usize,
+}
+
+/// the statements of a script, and whether the last of them is unfinished
+struct Split<'a> {
+    statements: Vec<Statement<'a>>,
+    incomplete: bool,
+}
+
+/// a line ending in one of these carries on to the next line
+const CONTINUATIONS: [char; 12] = ['+', '-', '*', '/', '%', '^', '=', '<', '>', '!', ',', ':'];
+const CONTINUATION_WORDS: [&str; 5] = ["given", "where", "sampled", "seed", "in"];
+
+fn continues(code: &str) -> bool {
+    let code = code.trim_end();
+    code.ends_with(&CONTINUATIONS[..]) || CONTINUATION_WORDS.iter().any(|word| {
+        code.ends_with(word) && !code[..code.len() - word.len()].ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+/// split a script on `;` and on newlines, except where a newline falls inside brackets or a string,
+/// or follows an operator, so that an expression may carry on over several lines
+fn split(src: &str) -> Split<'_> {
+    let mut statements = vec![];
+    let (mut depth, mut in_string, mut escaped, mut in_comment) = (0, false, false, false);
+    // the start of the current statement, and the end of its code so far, ignoring comments
+    let (mut start, mut code_end) = (0, 0);
+    let mut push = |start: usize, code_end: usize| {
+        let text = &src[start..code_end.max(start)];
+        let trimmed = text.trim_start();
+        if !trimmed.trim_end().is_empty() {
+            statements.push(Statement { src: trimmed.trim_end(), offset: start + text.len() - trimmed.len() });
+        }
+    };
+    for (i, c) in src.char_indices() {
+        if in_comment && c != '\n' {
+            continue;
+        }
+        in_comment = false;
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            code_end = i + c.len_utf8();
+            continue;
+        }
+        match c {
+            '#' => in_comment = true,
+            ';' if depth <= 0 => {
+                push(start, code_end);
+                start = i + 1;
+                code_end = start;
+            }
+            '\n' if depth <= 0 && !continues(&src[start..code_end.max(start)]) => {
+                push(start, code_end);
+                start = i + 1;
+                code_end = start;
+            }
+            _ => {
+                match c {
+                    '"' => in_string = true,
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth -= 1,
+                    _ => {}
+                }
+                if !c.is_whitespace() {
+                    code_end = i + c.len_utf8();
+                }
+            }
+        }
+    }
+    let incomplete = depth > 0 || in_string || continues(&src[start..code_end.max(start)]);
+    push(start, code_end);
+    Split { statements, incomplete }
+}
+
+pub fn split_statements(src: &str) -> Vec<Statement<'_>> {
+    split(src).statements
+}
+
+/// whether more lines are needed to finish the last statement, i.e. it has an unclosed bracket
+pub fn is_incomplete(src: &str) -> bool {
+    split(src).incomplete
+}
+
+/// the line and column of a byte offset, both counting from 1
+pub fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+/// point an error from one statement at its place in the whole script
+/// only parse errors know where in the statement they happened, so the others just give the line it starts on
+fn locate(err: Error, name: &str, src: &str, statement: &Statement) -> Error {
+    let (line, column) = line_col(src, statement.offset + err.opt_span.map_or(0, |(start, _)| start));
+    let text = src.lines().nth(line - 1).unwrap_or("");
+    let mut located = match err.opt_span {
+        Some(_) => err.concat(fail!("{}:{}:{}: statement failed\n{}\n{}^", name, line, column, text, " ".repeat(column - 1))),
+        None => err.concat(fail!("{}:{}: statement failed\n{}", name, line, text)),
+    };
+    // the span only makes sense within the statement, not whatever line started the script
+    located.opt_span = None;
+    located
+}
+
+/// run every statement of a script in order, stopping at the first error, and return the last value
+pub fn run(src: &str, name: &str, env: &mut Env) -> Result<Symbol, Error> {
+    let mut last = Symbol::Nil;
+    for statement in split_statements(src) {
+        last = crate::parse_analyze_evaluate(statement.src, env)
+            .map_err(|err| locate(err, name, src, &statement))?;
+    }
+    Ok(last)
+}
+
+/// read and run the script at `path`
+pub fn run_file(path: &str, env: &mut Env) -> Result<Symbol, Error> {
+    let resolved = env.resolve_path(path);
+    let name = resolved.display().to_string();
+    let src = std::fs::read_to_string(&resolved).map_err(|err| fail!("could not read {}: {}", name, err))?;
+    let canonical = resolved.canonicalize().map_err(|err| fail!("could not read {}: {}", name, err))?;
+    env.start_loading(canonical)?;
+    let result = run(&src, &name, env);
+    env.finish_loading();
+    result
+}
+
+/// run the script at `path` in a namespace of its own, binding it as `name`
+pub fn import_file(path: &str, name: &str, env: &mut Env) -> Result<(), Error> {
+    let mut module = env.new_module();
+    run_file(path, &mut module)?;
+    env.bind_module(name.to_string(), module);
+    Ok(())
+}
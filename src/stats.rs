@@ -0,0 +1,156 @@
+use crate::distr::{Distr, KeyType, ProbType};
+use crate::error::Error;
+
+/// the outcome of a goodness-of-fit test
+/// # Fields
+/// * `statistic` - how far the observed rolls are from the expected distribution
+/// * `p_value` - the chance of seeing a statistic at least this large if the dice were fair
+#[derive(Clone, Debug)]
+pub struct FitTest {
+    pub name: &'static str,
+    pub statistic: ProbType,
+    pub p_value: ProbType,
+}
+
+/// read a log of observed rolls, separated by whitespace or commas, ignoring anything after a `#`
+pub fn parse_rolls(src: &str) -> Result<Vec<KeyType>, Error> {
+    let mut rolls = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        for field in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|field| !field.is_empty()) {
+            let roll = field
+                .parse::<KeyType>()
+                .map_err(|_| fail!("line {}: could not read {:?} as a roll", i + 1, field))?;
+            rolls.push(roll);
+        }
+    }
+    Ok(rolls)
+}
+
+/// the natural log of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFS: [f64; 6] = [
+        76.180_091_729_471_46, -86.505_320_329_416_77, 24.014_098_240_830_91,
+        -1.231_739_572_450_155, 0.001_208_650_973_866_179, -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFS.iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |acc, (i, c)| acc + c / (x + 1.0 + i as f64));
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// the regularized upper incomplete gamma function Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let log_prefix = -x + a * x.ln() - ln_gamma(a);
+    if x < a + 1.0 {
+        // the series for P(a, x) converges quickly here
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        1.0 - sum * log_prefix.exp()
+    } else {
+        // otherwise use the continued fraction for Q(a, x), by Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny { d = tiny; }
+            c = b + an / c;
+            if c.abs() < tiny { c = tiny; }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        log_prefix.exp() * h
+    }
+}
+
+/// the chance that the Kolmogorov distribution exceeds `lambda`
+fn kolmogorov_q(lambda: f64) -> f64 {
+    if lambda < 1e-3 {
+        return 1.0;
+    }
+    let sum: f64 = (1..=100)
+        .map(|k| {
+            let sign = if k % 2 == 1 { 1.0 } else { -1.0 };
+            sign * (-2.0 * (k * k) as f64 * lambda * lambda).exp()
+        })
+        .sum();
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+/// Pearson's chi-square test of the observed counts against those expected from `expected`
+pub fn chi_square(rolls: &[KeyType], expected: &Distr) -> Result<FitTest, Error> {
+    if rolls.is_empty() {
+        return Err(fail!("there are no rolls to test"));
+    }
+    let observed = Distr::empirical(rolls)?;
+    let n = rolls.len() as ProbType;
+    let outcomes = expected.iter().filter(|x| expected.prob(**x) > 0.0).count();
+    if outcomes < 2 {
+        return Err(fail!("the expected distribution needs at least two possible outcomes"));
+    }
+    if observed.iter().any(|x| expected.prob(*x) <= 0.0) {
+        // a roll that should be impossible is conclusive on its own
+        return Ok(FitTest { name: "chi-square", statistic: ProbType::INFINITY, p_value: 0.0 });
+    }
+    let statistic = expected.iter()
+        .map(|x| {
+            let expected_count = n * expected.prob(*x);
+            let observed_count = n * observed.prob(*x);
+            (observed_count - expected_count).powi(2) / expected_count
+        })
+        .sum::<ProbType>();
+    let dof = (outcomes - 1) as f64;
+    Ok(FitTest { name: "chi-square", statistic, p_value: gamma_q(dof / 2.0, statistic / 2.0) })
+}
+
+/// the Kolmogorov-Smirnov test, comparing the observed and expected cdfs at every outcome
+pub fn kolmogorov_smirnov(rolls: &[KeyType], expected: &Distr) -> Result<FitTest, Error> {
+    if rolls.is_empty() {
+        return Err(fail!("there are no rolls to test"));
+    }
+    let observed = Distr::empirical(rolls)?;
+    let statistic = observed.iter()
+        .chain(expected.iter())
+        .map(|x| (observed.cdf(*x) - expected.cdf(*x)).abs())
+        .fold(0.0, ProbType::max);
+    let sqrt_n = (rolls.len() as f64).sqrt();
+    let p_value = kolmogorov_q((sqrt_n + 0.12 + 0.11 / sqrt_n) * statistic);
+    Ok(FitTest { name: "kolmogorov-smirnov", statistic, p_value })
+}
+
+pub fn fairness_view(rolls: &[KeyType], tests: &[FitTest], expected: &Distr) -> String {
+    let mut s = format!("{} rolls\n{:<20} {:>12} {:>10}\n", rolls.len(), "test", "statistic", "p-value");
+    for test in tests {
+        s.push_str(&format!("{:<20} {:>12.4} {:>10.5}\n", test.name, test.statistic, test.p_value));
+    }
+    let smallest = expected.iter()
+        .map(|x| expected.prob(*x))
+        .filter(|p| *p > 0.0)
+        .fold(1.0, ProbType::min);
+    if rolls.len() as ProbType * smallest < 5.0 {
+        s.push_str("warning: some outcomes are expected fewer than 5 times, so the chi-square p-value is unreliable\n");
+    }
+    // the test is conservative for dice, since their cdfs jump rather than rise smoothly
+    s.push_str("note: kolmogorov-smirnov p-values overstate fairness for discrete outcomes\n");
+    s
+}
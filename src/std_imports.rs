@@ -1,70 +1,679 @@
 use crate::type_info::{Type};
+use crate::symbols::Symbol;
 use crate::error::Error;
+use crate::distr::{Distr, KeyType, ProbType};
 use crate::closures::FnType;
+use crate::render::{self, HistOptions, Orientation, Scale};
+use crate::svg::{self, ChartKind};
+use crate::stats;
+use crate::audit;
+use crate::rolling::Roller;
+use crate::script;
+use crate::sampling::{self, Sampler};
 
 use crate::env::Env;
 
 impl Env {
     pub fn import_arithmetic(&mut self) -> &mut Self {
+        let unary = Type::Fn(fn_type!(Type::Num, -> Type::Distr));
+        let binary = Type::Fn(fn_type!(Type::Num, Type::Num, -> Type::Distr));
+        // map: Fn(Seq<a>, Fn(a) -> b) -> Seq<b>, and fold: Fn(Seq<a>, b, Fn(b, a) -> b) -> b
+        let (a, b) = (Type::Var("a".to_string()), Type::Var("b".to_string()));
+        let (seq_a, seq_b) = (Type::Seq(Box::new(a.clone())), Type::Seq(Box::new(b.clone())));
+        let map_fn = Type::Fn(FnType { in_types: vec![a.clone()], out_type: Box::new(b.clone()) });
+        let fold_fn = Type::Fn(FnType { in_types: vec![b.clone(), a], out_type: Box::new(b.clone()) });
+        let (fold_seq, fold_init) = (seq_a.clone(), b.clone());
         self
             // MULTIPLICATION
-            .bind_fn_var("mul".to_string(), |args, _| {
-                args[0].try_to_distr().and_then(|left|
-                    args[1].try_to_distr().and_then(|right|
-                        Ok(left.as_ref().combine_op(right.as_ref(), |x, y| x * y).into())
-                    )
-                )
+            .bind_fn_var("mul".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| checked_op(x, "*", y, x.checked_mul(y)))
             }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
             // DIVISION
-            .bind_fn_var("div".to_string(), |args, _| {
-                args[0].try_to_distr().and_then(|left|
-                    args[1].try_to_distr().and_then(|right|
-                        Ok(left.as_ref().combine_op(right.as_ref(), |x, y| x / y).into())
-                    )
-                )
+            .bind_fn_var("div".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| nonzero_divisor(x, y).and_then(|y| checked_op(x, "/", y, x.checked_div(y))))
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // DIVISION, ROUNDING DOWN
+            .bind_fn_var("floor-div".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| nonzero_divisor(x, y).and_then(|y| checked_op(x, "//", y, floor_div(x, y))))
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // DIVISION, ROUNDING UP
+            .bind_fn_var("ceil-div".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| nonzero_divisor(x, y).and_then(|y| checked_op(x, "/^", y, ceil_div(x, y))))
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // REMAINDER, which is never negative
+            .bind_fn_var("mod".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| nonzero_divisor(x, y).and_then(|y| checked_op(x, "%", y, x.checked_rem_euclid(y))))
             }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // EXPONENTIATION
+            .bind_fn_var("pow".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| {
+                    if y < 0 {
+                        return Err(fail!("can not raise {} to the negative power {}", x, y));
+                    }
+                    x.checked_pow(y as u32).ok_or_else(|| fail!("{} ^ {} is too large", x, y))
+                })
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // NEGATION
+            .bind_fn_var("neg".to_string(), |args, _| {
+                Ok(args[0].try_to_distr()?.map_fallible_op(|x| x.checked_neg().ok_or_else(|| fail!("-({}) is too large", x)))?.into())
+            }, fn_type!(Type::Distr, -> Type::Distr))
             // SUBTRACTION
-            .bind_fn_var("sub".to_string(), |args, _| {
-                args[0].try_to_distr().and_then(|left|
-                    args[1].try_to_distr().and_then(|right|
-                        Ok(left.as_ref().combine_op(right.as_ref(), |x, y| x - y).into())
-                    )
-                )
+            .bind_fn_var("sub".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| checked_op(x, "-", y, x.checked_sub(y)))
             }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
+            // INCLUSIVE RANGE
+            .bind_fn_var("range-to".to_string(), |args, _| {
+                let start = args[0].try_to_num()?.into_owned();
+                let stop = args[1].try_to_num()?.into_owned();
+                Ok(Symbol::Seq((start..=stop).map(Symbol::from).collect()))
+            }, fn_type!(Type::Num, Type::Num, -> Type::Seq(Box::new(Type::Num))))
+            // MAP OVER A SEQUENCE
+            .bind_fn_var("map".to_string(), |args, env| {
+                args[0].try_to_seq()?
+                    .iter()
+                    .map(|item| args[1].call(std::slice::from_ref(item), env))
+                    .collect::<Result<Vec<Symbol>, Error>>()
+                    .map(Symbol::Seq)
+            }, fn_type!(seq_a, map_fn, -> seq_b))
+            // FOLD A SEQUENCE, from the left
+            .bind_fn_var("fold".to_string(), |args, env| {
+                args[0].try_to_seq()?
+                    .iter()
+                    .try_fold(args[1].clone(), |acc, item| args[2].call(&[acc, item.clone()], env))
+            }, fn_type!(fold_seq, fold_init, fold_fn, -> b))
+            // MAP OVER OUTCOMES
+            .bind_fn_var("map-outcomes".to_string(), |args, env| {
+                let distr = args[1].try_to_distr()?;
+                distr.map_fallible_op(|x| apply_to_nums(&args[0], &[x], env)).map(Symbol::from)
+            }, fn_type!(unary, Type::Distr, -> Type::Distr))
+            // COMBINE OUTCOMES
+            .bind_fn_var("combine".to_string(), |args, env| {
+                let left = args[1].try_to_distr()?;
+                let right = args[2].try_to_distr()?;
+                let pairs = match env.sampler_mut() {
+                    Some(sampler) if sampler.prefers(left.len() * right.len()) => sampler.draw_pairs(&left, &right),
+                    _ => return left.combine_fallible_op(right.as_ref(), |x, y| apply_to_nums(&args[0], &[x, y], env)).map(Symbol::from),
+                };
+                let outcomes = pairs.into_iter()
+                    .map(|(x, y)| apply_to_nums(&args[0], &[x, y], env))
+                    .collect::<Result<Vec<KeyType>, Error>>()?;
+                Distr::empirical(&outcomes).map(Symbol::from)
+            }, fn_type!(binary, Type::Distr, Type::Distr, -> Type::Distr))
             // ADDITION
-            .bind_fn_var("add".to_string(), |args, _| {
-                args[0].try_to_distr().and_then(|left|
-                    args[1].try_to_distr().and_then(|right|
-                        Ok(left.as_ref().combine_op(right.as_ref(), |x, y| x + y).into())
-                    )
-                )
+            .bind_fn_var("add".to_string(), |args, env| {
+                combine_fallible_args(&args, env, |x, y| checked_op(x, "+", y, x.checked_add(y)))
             }, fn_type!(Type::Distr, Type::Distr, -> Type::Distr))
         ;
         self
     }
     pub fn import_dice(&mut self) -> &mut Self {
+        let seq = Type::Seq(Box::new(Type::Any));
+        let pairs = seq.clone();
+        let sweep_fn = Type::Fn(fn_type!(Type::Num, -> Type::Distr));
+        let sweep_range = Type::Seq(Box::new(Type::Num));
+        let (sweep_percentiles, grid_xs, grid_ys) = (sweep_range.clone(), sweep_range.clone(), sweep_range.clone());
+        let grid_fn = Type::Fn(fn_type!(Type::Num, Type::Num, -> Type::Distr));
+        let thunk = Type::Fn(fn_type!(Type::Any, -> Type::Distr));
+        let roll_thunk = thunk.clone();
+        let hist_seq = Type::Seq(Box::new(Type::Var("a".to_string())));
         self
             // MAKE DICE
-            .bind_fn_var("make-dice".to_string(), |args, _| {
-                args[0].try_to_num().and_then(|k|
+            .bind_fn_var("make-dice".to_string(), |args, env| {
+                args[0].try_to_distr().and_then(|k|
                     args[1].try_to_num().and_then(|n|
-                        Ok(crate::distr::Distr::stacked_unifs(k.into_owned(), n.into_owned()).into())
+                        make_dice(k.as_ref(), &Distr::unif(n.into_owned()), env)
                     )
                 )
-            }, fn_type!(Type::Num, Type::Num, -> Type::Distr))
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            // MAKE CUSTOM DICE
+            .bind_fn_var("make-custom-dice".to_string(), |args, env| {
+                args[0].try_to_distr().and_then(|k|
+                    args[1].try_to_seq().and_then(|seq| {
+                        let faces = seq.iter()
+                            .map(|face| face.try_to_num().map(|n| n.into_owned()))
+                            .collect::<Result<Vec<KeyType>, Error>>()?;
+                        if faces.is_empty() {
+                            return Err(fail!("a die needs at least one face"));
+                        }
+                        make_dice(k.as_ref(), &Distr::faces(&faces), env)
+                    })
+                )
+            }, fn_type!(Type::Distr, seq, -> Type::Distr))
+            // MAKE FATE DICE
+            .bind_fn_var("make-fate-dice".to_string(), |args, env| {
+                args[0].try_to_distr().and_then(|k|
+                    make_dice(k.as_ref(), &Distr::fate(), env)
+                )
+            }, fn_type!(Type::Distr, -> Type::Distr))
+            // KEEP THE HIGHEST OR LOWEST DICE
+            .bind_fn_var("keep-highest".to_string(), |args, env| {
+                make_kept(&args, true, env)
+            }, fn_type!(Type::Distr, Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("keep-lowest".to_string(), |args, env| {
+                make_kept(&args, false, env)
+            }, fn_type!(Type::Distr, Type::Distr, Type::Num, -> Type::Distr))
+            // SUCCESS POOL
+            .bind_fn_var("pool".to_string(), |args, env| {
+                let threshold = args[2].try_to_num()?.into_owned();
+                let die = args[1].try_to_distr()?;
+                let double_at = die.iter().max().map_or(threshold, |max| max + 1);
+                let subtract_at = die.iter().min().map_or(threshold, |min| min - 1);
+                make_pool(args[0].try_to_distr()?.as_ref(), die.as_ref(), threshold, double_at, subtract_at, env)
+            }, fn_type!(Type::Distr, Type::Distr, Type::Num, -> Type::Distr))
+            // SUCCESS POOL WITH DOUBLES AND SUBTRACTIONS
+            .bind_fn_var("pool-with".to_string(), |args, env| {
+                make_pool(
+                    args[0].try_to_distr()?.as_ref(),
+                    args[1].try_to_distr()?.as_ref(),
+                    args[2].try_to_num()?.into_owned(),
+                    args[3].try_to_num()?.into_owned(),
+                    args[4].try_to_num()?.into_owned(),
+                    env,
+                )
+            }, fn_type!(Type::Distr, Type::Distr, Type::Num, Type::Num, Type::Num, -> Type::Distr))
+            // CONDITIONING
+            .bind_fn_var("given-at-least".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x >= k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("given-above".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x > k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("given-at-most".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x <= k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("given-below".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x < k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("given-equal".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x == k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("given-not-equal".to_string(), |args, _| {
+                let k = args[1].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.given(|x| x != k).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            // TRUNCATION
+            .bind_fn_var("clamp".to_string(), |args, _| {
+                let lo = args[1].try_to_num()?.into_owned();
+                let hi = args[2].try_to_num()?.into_owned();
+                args[0].try_to_distr()?.clamp(lo, hi).map(Symbol::from)
+            }, fn_type!(Type::Distr, Type::Num, Type::Num, -> Type::Distr))
+            .bind_fn_var("min-at".to_string(), |args, _| {
+                let lo = args[1].try_to_num()?.into_owned();
+                Ok(args[0].try_to_distr()?.min_at(lo).into())
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            .bind_fn_var("max-at".to_string(), |args, _| {
+                let hi = args[1].try_to_num()?.into_owned();
+                Ok(args[0].try_to_distr()?.max_at(hi).into())
+            }, fn_type!(Type::Distr, Type::Num, -> Type::Distr))
+            // WEIGHTED MIXTURES
+            .bind_fn_var("mix".to_string(), |args, _| {
+                let pairs = args[0].try_to_seq()?
+                    .iter()
+                    .map(|pair| match pair.try_to_seq()? {
+                        [weight, distr] => Ok((weight.try_to_prob()?, distr.try_to_distr()?.into_owned())),
+                        _ => Err(fail!("expected a pair of [weight, distr], found {}", pair.repr())),
+                    })
+                    .collect::<Result<Vec<(ProbType, Distr)>, Error>>()?;
+                let parts = pairs.iter().map(|(w, distr)| (*w, distr)).collect::<Vec<(ProbType, &Distr)>>();
+                Distr::mixture(&parts).map(Symbol::from)
+            }, fn_type!(pairs, -> Type::Distr))
+            .bind_fn_var("either".to_string(), |args, _| {
+                let p = args[0].try_to_prob()?;
+                let first = args[1].try_to_distr()?;
+                let second = args[2].try_to_distr()?;
+                Distr::mixture(&[(p, first.as_ref()), (1.0 - p, second.as_ref())]).map(Symbol::from)
+            }, fn_type!(Type::Prob, Type::Distr, Type::Distr, -> Type::Distr))
+            // OPPOSED CHECKS
+            .bind_fn_var("beats".to_string(), |args, _| {
+                let (win, _, _) = args[0].try_to_distr()?.compare(args[1].try_to_distr()?.as_ref());
+                Ok(win.into())
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Prob))
+            .bind_fn_var("ties".to_string(), |args, _| {
+                let (_, tie, _) = args[0].try_to_distr()?.compare(args[1].try_to_distr()?.as_ref());
+                Ok(tie.into())
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Prob))
+            // [P(attacker wins), P(defender wins)], with ties going to whoever the tie rule names,
+            // i.e. `contest (d20 + 2) d20 "defender"`
+            .bind_fn_var("contest".to_string(), |args, _| {
+                let (win, tie, loss) = args[0].try_to_distr()?.compare(args[1].try_to_distr()?.as_ref());
+                let (win, loss) = break_ties(win, tie, loss, args[2].try_to_str()?)?;
+                Ok(Symbol::Seq(vec![win.into(), loss.into()]))
+            }, fn_type!(Type::Distr, Type::Distr, Type::Str, -> Type::Seq(Box::new(Type::Prob))))
+            // COMPARISON REPORT
+            .bind_fn_var("compare".to_string(), |args, _| {
+                let left = args[0].try_to_distr()?;
+                println!("{}", left.compare_view(args[1].try_to_distr()?.as_ref()));
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Nil))
+            // 1 if the first distribution stochastically dominates the second, otherwise 0
+            .bind_fn_var("dominates".to_string(), |args, _| {
+                let left = args[0].try_to_distr()?;
+                Ok(Symbol::Num(left.dominates(args[1].try_to_distr()?.as_ref()) as KeyType))
+            }, fn_type!(Type::Distr, Type::Distr, -> Type::Num))
+            // PARAMETER SWEEPS
+            .bind_fn_var("sweep".to_string(), |args, env| {
+                let percentiles = try_to_nums(&args[2])?;
+                let rows = try_to_nums(&args[1])?
+                    .into_iter()
+                    .map(|x| Ok((x, args[0].call(&[x.into()], env)?.try_to_distr()?.into_owned())))
+                    .collect::<Result<Vec<(KeyType, Distr)>, Error>>()?;
+                println!("{}", Distr::sweep_view(&rows, &percentiles));
+                Ok(Symbol::Nil)
+            }, fn_type!(sweep_fn, sweep_range, sweep_percentiles, -> Type::Nil))
+            .bind_fn_var("sweep-grid".to_string(), |args, env| {
+                let xs = try_to_nums(&args[1])?;
+                let ys = try_to_nums(&args[2])?;
+                let cells = xs.iter()
+                    .map(|x| ys.iter()
+                        .map(|y| Ok(args[0].call(&[(*x).into(), (*y).into()], env)?.try_to_distr()?.into_owned()))
+                        .collect::<Result<Vec<Distr>, Error>>())
+                    .collect::<Result<Vec<Vec<Distr>>, Error>>()?;
+                println!("{}", Distr::grid_view(&xs, &ys, &cells));
+                Ok(Symbol::Nil)
+            }, fn_type!(grid_fn, grid_xs, grid_ys, -> Type::Nil))
             // TABLE VIEW
             .bind_fn_var("table".to_string(), |args,_| {
-                args[0].try_to_distr().and_then(|distr|
-                    Ok(println!("{}", distr.table_view()).into())
-                )
+                args[0].try_to_distr().map(|distr| {
+                    println!("{}", distr.table_view());
+                    Symbol::Nil
+                })
             }, fn_type!(Type::Distr, -> Type::Nil))
             // HIST VIEW
+            // draws a sequence of distributions side by side, each either unlabeled or a `[label, distr]` pair,
+            // i.e. `hist [["longsword", d8 + 3], ["greatsword", 2d6 + 3]]`, or a single one on its own, i.e. `hist [d20]`
             .bind_fn_var("hist".to_string(), |args, _| {
-                args[0].try_to_distr().and_then(|distr|
-                    Ok(println!("{}", distr.hist_view()).into())
-                )
+                match args[0].try_to_seq()? {
+                    [Symbol::Seq(_)] => {}
+                    [single] => {
+                        println!("{}", single.try_to_distr()?.hist_view());
+                        return Ok(Symbol::Nil);
+                    }
+                    _ => {}
+                }
+                let distrs = try_to_labeled_distrs(&args[0])?;
+                let labeled = distrs.iter().map(|(label, distr)| (label.clone(), distr)).collect::<Vec<(String, &Distr)>>();
+                println!("{}", Distr::multi_hist_view(&labeled, render::terminal_width()));
+                Ok(Symbol::Nil)
+            }, fn_type!(hist_seq, -> Type::Nil))
+            .bind_fn_var("hist-log".to_string(), |args, _| {
+                let options = HistOptions { scale: Scale::Log, ..HistOptions::default() };
+                println!("{}", render::histogram(args[0].try_to_distr()?.as_ref(), &options));
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Distr, -> Type::Nil))
+            .bind_fn_var("hist-vertical".to_string(), |args, _| {
+                let options = HistOptions { orientation: Orientation::Vertical, ..HistOptions::default() };
+                println!("{}", render::histogram(args[0].try_to_distr()?.as_ref(), &options));
+                Ok(Symbol::Nil)
             }, fn_type!(Type::Distr, -> Type::Nil))
+            // CSV EXPORT AND IMPORT
+            .bind_fn_var("save-csv".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                write_file(path, &args[1].try_to_distr()?.csv_view())
+            }, fn_type!(Type::Str, Type::Distr, -> Type::Nil))
+            .bind_fn_var("load-csv".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                Distr::from_csv(&read_file(path)?)
+                    .map(Symbol::from)
+                    .map_err(|err| err.concat(fail!("could not load {}", path)))
+            }, fn_type!(Type::Str, -> Type::Distr))
+            // APPROXIMATE EVALUATION
+            // evaluate a thunk with sampling switched on, i.e. `expr sampled 10000 seed 7`
+            .bind_fn_var("estimate".to_string(), |args, env| {
+                let trials = positive_trials(&args[0])?;
+                let seed = args[1].try_to_num()?.into_owned();
+                let previous = env.set_sampler(Some(Sampler::new(trials, seed as u64)));
+                let result = args[2].call(&[Symbol::Nil], env);
+                let sampler = env.set_sampler(previous).expect("sampler removed during estimate");
+                let distr = result?.try_to_distr()?.into_owned();
+                if sampler.approximated {
+                    print!("{}", sampling::estimate_view(&distr, trials));
+                } else {
+                    println!("small enough to compute exactly");
+                }
+                Ok(distr.into())
+            }, fn_type!(Type::Num, Type::Num, thunk, -> Type::Distr))
+            // sample for the rest of the session, or go back to exact evaluation with `sampling 0 0`
+            .bind_fn_var("sampling".to_string(), |args, env| {
+                let seed = args[1].try_to_num()?.into_owned();
+                if *args[0].try_to_num()? == 0 {
+                    env.set_sampler(None);
+                    println!("evaluating exactly");
+                } else {
+                    let trials = positive_trials(&args[0])?;
+                    env.set_sampler(Some(Sampler::new(trials, seed as u64)));
+                    println!("sampling {} trials with seed {}", trials, seed);
+                }
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Num, Type::Num, -> Type::Nil))
+            // ROLLING DICE
+            // roll a thunk's dice for real, i.e. `roll 4d6kh3 + 2`, showing each die and logging the result
+            .bind_fn_var("roll".to_string(), |args, env| {
+                let expr = args[0].try_to_str()?.to_string();
+                env.roller_mut().begin();
+                let rolled = args[1].call(&[Symbol::Nil], env)
+                    .and_then(|value| value.try_to_distr().map(|distr| distr.into_owned()))
+                    // anything still uncertain, such as a distribution literal, is drawn from as a whole
+                    .and_then(|distr| env.roller_mut().draw(&distr).ok_or_else(|| fail!("{} has no outcomes to roll", expr)));
+                let roller = env.roller_mut();
+                match rolled {
+                    Ok(total) => {
+                        println!("{}", roller.finish(&expr, total)?);
+                        Ok(total.into())
+                    }
+                    Err(err) => {
+                        roller.cancel(&expr)?;
+                        Err(err)
+                    }
+                }
+            }, fn_type!(Type::Str, roll_thunk, -> Type::Num))
+            // show the last n rolls of the session, or all of them with `roll-history 0`
+            .bind_fn_var("roll-history".to_string(), |args, env| {
+                let n = args[0].try_to_num()?.into_owned();
+                let history = &env.roller().history;
+                let skip = if n <= 0 { 0 } else { history.len().saturating_sub(n as usize) };
+                for (i, record) in history.iter().enumerate().skip(skip) {
+                    println!("{:>4}. {}", i + 1, record);
+                }
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Num, -> Type::Nil))
+            .bind_fn_var("export-rolls".to_string(), |args, env| {
+                write_file(args[0].try_to_str()?, &env.roller().history_csv())
+            }, fn_type!(Type::Str, -> Type::Nil))
+            .bind_fn_var("roll-seed".to_string(), |args, env| {
+                let seed = args[0].try_to_num()?.into_owned();
+                env.roller_mut().seed(seed as u64)?;
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Num, -> Type::Nil))
+            // VERIFIABLE ROLLS
+            // publish a commitment to a secret seed, then roll from that seed until it is revealed
+            .bind_fn_var("commit-rolls".to_string(), |args, env| {
+                let commitment = env.roller_mut().commit(args[0].try_to_str()?)?;
+                println!("commitment: {}", commitment);
+                Ok(Symbol::Str(commitment))
+            }, fn_type!(Type::Str, -> Type::Str))
+            .bind_fn_var("reveal-rolls".to_string(), |args, env| {
+                let seed = env.roller_mut().reveal(args[0].try_to_str()?)?;
+                println!("seed: {}", seed);
+                Ok(Symbol::Str(seed))
+            }, fn_type!(Type::Str, -> Type::Str))
+            // 1 if every revealed session in an audit log matches its commitment and every die it drew, otherwise 0
+            .bind_fn_var("verify-rolls".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                let sessions = audit::parse_log(&read_file(path)?)
+                    .map_err(|err| err.concat(fail!("could not verify {}", path)))?;
+                let mut verified = true;
+                for (i, session) in sessions.iter().enumerate() {
+                    let problems = verify_session(session);
+                    println!("session {} ({} rolls): {}", i + 1, session.entries.len(), if problems.is_empty() { "verified" } else { "FAILED" });
+                    for problem in problems.iter() {
+                        println!("    {}", problem);
+                    }
+                    verified &= problems.is_empty();
+                }
+                Ok(Symbol::Num(verified as KeyType))
+            }, fn_type!(Type::Str, -> Type::Num))
+            // SCRIPTS
+            // run every statement in a file, i.e. `run party.dnd`, returning the value of the last one
+            .bind_fn_var("run".to_string(), |args, env| {
+                script::run_file(args[0].try_to_str()?, env)
+            }, fn_type!(Type::Str, -> Type::Any))
+            // evaluate a script into a namespace of its own, i.e. `import "monsters.dnd" as m` then `m.goblin`
+            .bind_fn_var("import".to_string(), |args, env| {
+                script::import_file(args[0].try_to_str()?, args[1].try_to_str()?, env)?;
+                Ok(Symbol::Nil)
+            }, fn_type!(Type::Str, Type::Str, -> Type::Nil))
+            // OBSERVED ROLLS
+            .bind_fn_var("load-rolls".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                Distr::empirical(&read_rolls(&args[0])?)
+                    .map(Symbol::from)
+                    .map_err(|err| err.concat(fail!("could not load {}", path)))
+            }, fn_type!(Type::Str, -> Type::Distr))
+            // goodness-of-fit of a roll log, or a sequence of rolls, against the expected distribution
+            .bind_fn_var("fairness".to_string(), |args, _| {
+                let rolls = read_rolls(&args[0])?;
+                let expected = args[1].try_to_distr()?;
+                let tests = vec![stats::chi_square(&rolls, &expected)?, stats::kolmogorov_smirnov(&rolls, &expected)?];
+                print!("{}", stats::fairness_view(&rolls, &tests, &expected));
+                Ok(Symbol::Seq(tests.iter().map(|test| test.p_value.into()).collect()))
+            }, fn_type!(Type::Any, Type::Distr, -> Type::Seq(Box::new(Type::Prob))))
+            // SVG EXPORT
+            .bind_fn_var("export-svg".to_string(), |args, _| {
+                export_svg(&args, ChartKind::Bar)
+            }, fn_type!(Type::Str, Type::Str, Type::Any, -> Type::Nil))
+            .bind_fn_var("export-svg-cdf".to_string(), |args, _| {
+                export_svg(&args, ChartKind::Cdf)
+            }, fn_type!(Type::Str, Type::Str, Type::Any, -> Type::Nil))
         ;
+        // JSON EXPORT AND IMPORT
+        #[cfg(feature = "serde")]
         self
+            .bind_fn_var("save-json".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                write_file(path, &args[1].try_to_distr()?.json_view())
+            }, fn_type!(Type::Str, Type::Distr, -> Type::Nil))
+            .bind_fn_var("load-json".to_string(), |args, _| {
+                let path = args[0].try_to_str()?;
+                Distr::from_json(&read_file(path)?)
+                    .map(Symbol::from)
+                    .map_err(|err| err.concat(fail!("could not load {}", path)))
+            }, fn_type!(Type::Str, -> Type::Distr))
+        ;
+        self
+    }
+}
+/// share the ties of an opposed check out between the attacker and the defender:
+/// `"attacker"` or `"defender"` wins them, and `"reroll"` rolls again until someone wins
+fn break_ties(win: ProbType, tie: ProbType, loss: ProbType, rule: &str) -> Result<(ProbType, ProbType), Error> {
+    match rule {
+        "attacker" => Ok((win + tie, loss)),
+        "defender" => Ok((win, tie + loss)),
+        "reroll" if win + loss > 0.0 => Ok((win / (win + loss), loss / (win + loss))),
+        "reroll" => Err(fail!("a contest that always ties can not be rerolled")),
+        _ => Err(fail!("unknown tie rule {:?}, expected \"attacker\", \"defender\" or \"reroll\"", rule)),
+    }
+}
+/// read a sequence of numbers, such as a range of parameters
+fn try_to_nums(symbol: &Symbol) -> Result<Vec<KeyType>, Error> {
+    symbol.try_to_seq()?
+        .iter()
+        .map(|x| x.try_to_num().map(|n| n.into_owned()))
+        .collect()
+}
+
+/// read either a single distribution or a sequence of them, labelling each by its position
+/// unless it is given a label of its own, i.e. `[["fire", 2d6], ["cold", 3d4]]`
+fn try_to_labeled_distrs(symbol: &Symbol) -> Result<Vec<(String, Distr)>, Error> {
+    match *symbol {
+        Symbol::Seq(ref items) => items.iter()
+            .enumerate()
+            .map(|(i, item)| match item.try_to_seq() {
+                Ok([Symbol::Str(label), distr]) => distr.try_to_distr().map(|distr| (label.clone(), distr.into_owned())),
+                _ => item.try_to_distr().map(|distr| (format!("#{}", i + 1), distr.into_owned())),
+            })
+            .collect(),
+        _ => Ok(vec![("#1".to_string(), symbol.try_to_distr()?.into_owned())]),
+    }
+}
+
+/// write `args[2]` (one distribution or a sequence of them) to the svg file at `args[0]`, titled `args[1]`
+fn export_svg(args: &[Symbol], kind: ChartKind) -> Result<Symbol, Error> {
+    let path = args[0].try_to_str()?;
+    let distrs = try_to_labeled_distrs(&args[2])?;
+    let labeled = distrs.iter().map(|(label, distr)| (label.clone(), distr)).collect::<Vec<(String, &Distr)>>();
+    write_file(path, &svg::chart(&labeled, args[1].try_to_str()?, kind))
+}
+
+fn write_file(path: &str, contents: &str) -> Result<Symbol, Error> {
+    std::fs::write(path, contents).map_err(|err| fail!("could not write {}: {}", path, err))?;
+    Ok(Symbol::Nil)
+}
+
+/// replay the rolls of one audit log session, describing everything that does not match
+fn verify_session(session: &audit::Session) -> Vec<String> {
+    let seed = match session.seed {
+        Some(seed) => seed,
+        None => return vec!["the seed has not been revealed yet".to_string()],
+    };
+    if audit::commitment(&seed) != session.commitment {
+        return vec![format!("the revealed seed does not match the commitment {}", session.commitment)];
+    }
+    // replay the draws themselves rather than the expressions, which may use names that only the session had bound
+    let mut replay = Roller::replay(seed);
+    let mut problems = vec![];
+    for (i, entry) in session.entries.iter().enumerate() {
+        for (distr, outcomes) in entry.draws() {
+            let replayed = outcomes.iter().map(|_| replay.draw(distr)).collect::<Option<Vec<KeyType>>>();
+            if replayed.as_ref() != Some(outcomes) {
+                let replayed = replayed.map_or("nothing".to_string(), |replayed| format!("{:?}", replayed));
+                problems.push(format!("roll {} ({}): logged {:?} from {}, but replaying gives {}", i + 1, entry.expr(), outcomes, distr, replayed));
+            }
+        }
+    }
+    problems
+}
+
+/// read rolls either from a sequence of numbers, or from the roll log at a path
+fn read_rolls(symbol: &Symbol) -> Result<Vec<KeyType>, Error> {
+    match *symbol {
+        Symbol::Seq(_) => try_to_nums(symbol),
+        _ => {
+            let path = symbol.try_to_str()?;
+            stats::parse_rolls(&read_file(path)?).map_err(|err| err.concat(fail!("could not read rolls from {}", path)))
+        }
+    }
+}
+
+fn read_file(path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|err| fail!("could not read {}: {}", path, err))
+}
+
+/// apply a user supplied function to some outcomes, expecting a single number back
+fn apply_to_nums(func: &Symbol, nums: &[KeyType], env: &mut Env) -> Result<KeyType, Error> {
+    let args = nums.iter().map(|n| Symbol::Num(*n)).collect::<Vec<Symbol>>();
+    let result = func.call(&args, env)?;
+    result
+        .try_to_num()
+        .map(|n| n.into_owned())
+        .map_err(|err| err.concat(fail!("{} must produce a single number for {:?}", func.repr(), nums)))
+}
+
+/// roll `die` a (possibly random) number of times and sum the results
+fn make_dice(count: &Distr, die: &Distr, env: &mut Env) -> Result<Symbol, Error> {
+    if let Some(min_k) = count.iter().min().filter(|k| **k < 0) {
+        return Err(fail!("can not roll a negative number of dice ({})", min_k));
+    }
+    if env.roller().is_rolling() {
+        let roller = env.roller_mut();
+        let k = roller.draw(count).unwrap_or(0);
+        return Ok(roller.roll_dice(k, die, None).into());
+    }
+    if let Some(sampler) = env.sampler_mut() {
+        // the exact result takes about one convolution per possible die, each over a growing total
+        let max_k = count.iter().max().cloned().unwrap_or(0).max(0) as usize;
+        if sampler.prefers(max_k * max_k * die.len() * die.len()) {
+            return sampler.compound(count, die).map(Symbol::from);
+        }
+    }
+    match count.try_to_num() {
+        Ok(k) => Ok(Distr::stacked(k, die).into()),
+        Err(_) => Distr::compound(count, die).map(Symbol::from),
+    }
+}
+
+/// roll a (possibly random) number of `die`, totalling only the highest or lowest `args[2]` of them
+fn make_kept(args: &[Symbol], highest: bool, env: &mut Env) -> Result<Symbol, Error> {
+    let count = args[0].try_to_distr()?;
+    let die = args[1].try_to_distr()?;
+    let keep = args[2].try_to_num()?.into_owned();
+    if keep < 0 {
+        return Err(fail!("can not keep a negative number of dice ({})", keep));
+    }
+    if let Some(min_k) = count.iter().min().filter(|k| **k < 0) {
+        return Err(fail!("can not roll a negative number of dice ({})", min_k));
+    }
+    if env.roller().is_rolling() {
+        let roller = env.roller_mut();
+        let k = roller.draw(&count).unwrap_or(0);
+        return Ok(roller.roll_dice(k, &die, Some((keep as usize, highest))).into());
+    }
+    let parts = count.iter()
+        .map(|k| (count.prob(*k), Distr::kept(*k, &die, keep as usize, highest)))
+        .collect::<Vec<(ProbType, Distr)>>();
+    let parts = parts.iter().map(|(p, distr)| (*p, distr)).collect::<Vec<(ProbType, &Distr)>>();
+    Distr::mixture(&parts).map(Symbol::from)
+}
+
+/// the number of trials for the sampling mode, which must be positive
+fn positive_trials(symbol: &Symbol) -> Result<usize, Error> {
+    let trials = symbol.try_to_num()?.into_owned();
+    if trials <= 0 {
+        return Err(fail!("need a positive number of trials, found {}", trials));
+    }
+    Ok(trials as usize)
+}
+
+/// combine the first two arguments with `op`, which fails on some outcomes, such as dividing by zero or overflowing
+/// approximates when sampling is on and the exact result would be expensive
+fn combine_fallible_args<F>(args: &[Symbol], env: &mut Env, op: F) -> Result<Symbol, Error>
+  where F: Fn(KeyType, KeyType) -> Result<KeyType, Error> {
+    let left = args[0].try_to_distr()?;
+    let right = args[1].try_to_distr()?;
+    match env.sampler_mut() {
+        Some(sampler) if sampler.prefers(left.len() * right.len()) => {
+            let outcomes = sampler.draw_pairs(&left, &right)
+                .into_iter()
+                .map(|(x, y)| op(x, y))
+                .collect::<Result<Vec<KeyType>, Error>>()?;
+            Distr::empirical(&outcomes).map(Symbol::from)
+        }
+        _ => left.combine_fallible_op(&right, op).map(Symbol::from),
+    }
+}
+
+fn nonzero_divisor(x: KeyType, y: KeyType) -> Result<KeyType, Error> {
+    if y == 0 {
+        Err(fail!("can not divide {} by zero", x))
+    } else {
+        Ok(y)
+    }
+}
+
+/// report an operation whose result does not fit, like `2147483647 + 1` or `i32::MIN / -1`
+fn checked_op(x: KeyType, op: &str, y: KeyType, result: Option<KeyType>) -> Result<KeyType, Error> {
+    result.ok_or_else(|| fail!("{} {} {} is too large", x, op, y))
+}
+
+/// divide, rounding towards negative infinity rather than towards zero
+fn floor_div(x: KeyType, y: KeyType) -> Option<KeyType> {
+    let q = x.checked_div(y)?;
+    if x % y != 0 && (x < 0) != (y < 0) { q.checked_sub(1) } else { Some(q) }
+}
+
+/// divide, rounding towards positive infinity rather than towards zero
+fn ceil_div(x: KeyType, y: KeyType) -> Option<KeyType> {
+    let q = x.checked_div(y)?;
+    if x % y != 0 && (x < 0) == (y < 0) { q.checked_add(1) } else { Some(q) }
+}
+
+/// count the successes from rolling `die` a (possibly random) number of times
+fn make_pool(count: &Distr, die: &Distr, threshold: KeyType, double_at: KeyType, subtract_at: KeyType, env: &mut Env) -> Result<Symbol, Error> {
+    if env.roller().is_rolling() {
+        let roller = env.roller_mut();
+        let k = roller.draw(count).unwrap_or(0);
+        let successes: KeyType = roller.roll_faces(k, die, None)
+            .into_iter()
+            .map(|x| Distr::success_score(x, threshold, double_at, subtract_at))
+            .sum();
+        return Ok(successes.into());
+    }
+    match count.try_to_num() {
+        Ok(k) => Ok(Distr::pool(k, die, threshold, double_at, subtract_at).into()),
+        Err(_) => Distr::compound(count, &die.successes(threshold, double_at, subtract_at)).map(Symbol::from),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,131 @@
+use crate::distr::{Distr, KeyType, ProbType};
+
+/// bars show the probability of each outcome, lines show the probability of rolling at most each outcome
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChartKind {
+    Bar,
+    Cdf,
+}
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 440.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 160.0;
+const MARGIN_TOP: f64 = 50.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+const COLORS: [&str; 6] = ["#4e79a7", "#f28e2b", "#59a14f", "#e15759", "#b07aa1", "#76b7b2"];
+
+/// escape text so that it can be placed inside an svg element
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// the spacing between ticks, so that there are roughly `count` of them across `span`
+fn tick_step(span: f64, count: f64) -> f64 {
+    let raw = (span / count).max(1e-12);
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// draw one or more distributions as a standalone svg document, with axes, a legend and each mean marked
+pub fn chart(distrs: &[(String, &Distr)], title: &str, kind: ChartKind) -> String {
+    let min_x = distrs.iter().filter_map(|(_, distr)| distr.iter().min()).min().cloned().unwrap_or(0);
+    let max_x = distrs.iter().filter_map(|(_, distr)| distr.iter().max()).max().cloned().unwrap_or(0);
+    let max_p = match kind {
+        ChartKind::Bar => distrs.iter()
+            .flat_map(|(_, distr)| distr.iter().map(move |x| distr.prob(*x)))
+            .fold(0.0, ProbType::max),
+        ChartKind::Cdf => 1.0,
+    };
+    let max_p = if max_p > 0.0 { max_p } else { 1.0 };
+
+    let plot_w = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_h = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    // each outcome gets a slot of equal width, centered on its position
+    let slots = (max_x - min_x + 1) as f64;
+    let slot_w = plot_w / slots;
+    let to_x = |x: f64| MARGIN_LEFT + (x - min_x as f64 + 0.5) * slot_w;
+    let to_y = |p: f64| MARGIN_TOP + plot_h * (1.0 - p / max_p);
+
+    let mut s = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        w = WIDTH, h = HEIGHT);
+    s.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n", WIDTH, HEIGHT));
+    s.push_str(&format!("<text x=\"{}\" y=\"{}\" font-size=\"18\" text-anchor=\"middle\">{}</text>\n",
+                        MARGIN_LEFT + plot_w / 2.0, MARGIN_TOP / 2.0 + 6.0, escape(title)));
+
+    // axes
+    s.push_str(&format!("<line x1=\"{l}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\" stroke=\"black\"/>\n",
+                        l = MARGIN_LEFT, r = MARGIN_LEFT + plot_w, b = MARGIN_TOP + plot_h));
+    s.push_str(&format!("<line x1=\"{l}\" y1=\"{t}\" x2=\"{l}\" y2=\"{b}\" stroke=\"black\"/>\n",
+                        l = MARGIN_LEFT, t = MARGIN_TOP, b = MARGIN_TOP + plot_h));
+    let x_step = tick_step(slots, 10.0).max(1.0) as KeyType;
+    let first_tick = min_x + (x_step - min_x.rem_euclid(x_step)) % x_step;
+    for x in (first_tick..=max_x).step_by(x_step as usize) {
+        s.push_str(&format!("<line x1=\"{x}\" y1=\"{b}\" x2=\"{x}\" y2=\"{b5}\" stroke=\"black\"/>\n<text x=\"{x}\" y=\"{b18}\" text-anchor=\"middle\">{label}</text>\n",
+                            x = to_x(x as f64), b = MARGIN_TOP + plot_h, b5 = MARGIN_TOP + plot_h + 5.0, b18 = MARGIN_TOP + plot_h + 18.0, label = x));
+    }
+    let p_step = tick_step(max_p, 5.0);
+    let mut p = 0.0;
+    while p <= max_p + 1e-12 {
+        s.push_str(&format!("<line x1=\"{l5}\" y1=\"{y}\" x2=\"{l}\" y2=\"{y}\" stroke=\"black\"/>\n<text x=\"{l8}\" y=\"{y4}\" text-anchor=\"end\">{label:.3}</text>\n",
+                            l5 = MARGIN_LEFT - 5.0, l = MARGIN_LEFT, l8 = MARGIN_LEFT - 8.0, y = to_y(p), y4 = to_y(p) + 4.0, label = p));
+        p += p_step;
+    }
+    s.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">outcome</text>\n", MARGIN_LEFT + plot_w / 2.0, HEIGHT - 15.0));
+    let y_label = match kind {
+        ChartKind::Bar => "P(X = x)",
+        ChartKind::Cdf => "P(X ≤ x)",
+    };
+    s.push_str(&format!("<text x=\"18\" y=\"{y}\" text-anchor=\"middle\" transform=\"rotate(-90 18 {y})\">{label}</text>\n",
+                        y = MARGIN_TOP + plot_h / 2.0, label = y_label));
+
+    // data
+    let bar_w = slot_w * 0.8 / distrs.len().max(1) as f64;
+    for (i, (_, distr)) in distrs.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        match kind {
+            ChartKind::Bar => {
+                for x in distr.iter() {
+                    let left = to_x(*x as f64) - slot_w * 0.4 + bar_w * i as f64;
+                    let top = to_y(distr.prob(*x));
+                    s.push_str(&format!("<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"{}\" fill-opacity=\"0.85\"/>\n",
+                                        left, top, bar_w, MARGIN_TOP + plot_h - top, color));
+                }
+            }
+            ChartKind::Cdf => {
+                // a step function, flat between outcomes and jumping at each one
+                let mut points = vec![format!("{:.2},{:.2}", MARGIN_LEFT, to_y(0.0))];
+                let mut total = 0.0;
+                for x in distr.iter() {
+                    let at = to_x(*x as f64);
+                    points.push(format!("{:.2},{:.2}", at, to_y(total)));
+                    total += distr.prob(*x);
+                    points.push(format!("{:.2},{:.2}", at, to_y(total)));
+                }
+                points.push(format!("{:.2},{:.2}", MARGIN_LEFT + plot_w, to_y(total)));
+                s.push_str(&format!("<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n", points.join(" "), color));
+            }
+        }
+        let mean = to_x(distr.mean());
+        s.push_str(&format!("<line x1=\"{m:.2}\" y1=\"{t}\" x2=\"{m:.2}\" y2=\"{b}\" stroke=\"{c}\" stroke-width=\"1.5\" stroke-dasharray=\"6 4\"/>\n",
+                            m = mean, t = MARGIN_TOP, b = MARGIN_TOP + plot_h, c = color));
+    }
+
+    // legend
+    for (i, (label, distr)) in distrs.iter().enumerate() {
+        let y = MARGIN_TOP + 10.0 + 36.0 * i as f64;
+        let x = MARGIN_LEFT + plot_w + 15.0;
+        s.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{}\"/>\n", x, y - 10.0, COLORS[i % COLORS.len()]));
+        s.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n", x + 18.0, y, escape(label)));
+        s.push_str(&format!("<text x=\"{}\" y=\"{}\" fill=\"#555\">mean {:.3}</text>\n", x + 18.0, y + 15.0, distr.mean()));
+    }
+    s.push_str("</svg>\n");
+    s
+}
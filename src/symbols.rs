@@ -1,80 +1,103 @@
 use std::borrow::Cow;
 
-use crate::distr::{KeyType, Distr};
-use crate::type_info::{Type};
-use crate::env::Env;
+use crate::distr::{KeyType, ProbType, Distr};
+use crate::type_info::{Type, Bindings};
+use crate::env::{Env, Frame};
 use crate::error::Error;
-use crate::closures::{FnVal};
+use crate::closures::{FnVal, FnType};
 
 #[derive(Clone, Debug)]
 pub enum Symbol {
     Nil,
-    Text(String),
+    /// a name to look up in the environment, i.e. `goblin` or `monsters.goblin`
+    Ident(String),
+    /// a string literal, i.e. `"chart.svg"`
+    Str(String),
     Num(KeyType),
+    Prob(ProbType),
     Distr(Distr),
     Seq(Vec<Symbol>),
     Fn(FnVal),
+    /// a user defined function of one argument, i.e. `\x -> x + 1`
+    /// functions of several arguments are written as nested lambdas
+    Lambda{param: String, param_type: Option<Type>, body: Box<Symbol>},
+    /// `value` bound to `name` while evaluating `body`, i.e. `let x = d20 + 5 in x + x`
+    Let{name: String, def_type: Option<Type>, value: Box<Symbol>, body: Box<Symbol>},
     /// # Fields
     ///  target - the function to apply
     ///  args - the args to curry into the function
     ///
     /// the target should be evaluated to be a Symbol::Fn
     Apply{target: Box<Symbol>, args: Vec<Symbol>},
-    Assigner{name: String, def_type: Option<String>, expr: Box<Symbol>},
+    Assigner{name: String, def_type: Option<Type>, expr: Box<Symbol>},
 }
 
 impl Symbol {
     pub fn into_boxed(self) -> Box<Symbol> {
         Box::new(self)
     }
-    pub fn try_to_distr(&self) -> Result<Cow<Distr>, Error> {
+    pub fn try_to_distr(&self) -> Result<Cow<'_, Distr>, Error> {
         match *self {
             Symbol::Distr(ref d) => Ok(Cow::Borrowed(d)),
             Symbol::Num(num) => Ok(Cow::Owned(num.into())),
             _ => Err(fail!("{} is not a distr", self.repr())),
         }
     }
-    pub fn try_to_num(&self) -> Result<Cow<KeyType>, Error> {
+    pub fn try_to_num(&self) -> Result<Cow<'_, KeyType>, Error> {
         match *self {
             Symbol::Num(num) => Ok(Cow::Owned(num)),
             Symbol::Distr(ref d) => Ok(Cow::Owned(d.try_to_num()?)),
             _ => Err(fail!("{} is not a number", self.repr())),
         }
     }
+    pub fn try_to_prob(&self) -> Result<ProbType, Error> {
+        match *self {
+            Symbol::Prob(p) => Ok(p),
+            Symbol::Num(n) if n == 0 || n == 1 => Ok(n as ProbType),
+            _ => Err(fail!("{} is not a probability", self.repr())),
+        }
+    }
+    pub fn try_to_seq(&self) -> Result<&[Symbol], Error> {
+        match *self {
+            Symbol::Seq(ref v) => Ok(v),
+            _ => Err(fail!("{} is not a sequence", self.repr()))
+        }
+    }
     pub fn try_to_str(&self) -> Result<&str, Error> {
         match *self {
-            Symbol::Text(ref s) => Ok(s),
+            Symbol::Str(ref s) => Ok(s),
             _ => Err(fail!("{} is not a string", self.repr()))
         }
     }
     pub fn repr(&self) -> String {
         match *self {
-            Symbol::Nil => format!("Nil"),
-            Symbol::Text(ref s) => format!("{}", s),
+            Symbol::Nil => "Nil".to_string(),
+            Symbol::Ident(ref s) => s.clone(),
+            Symbol::Str(ref s) => format!("{:?}", s),
             Symbol::Num(n) => format!("{}", n),
-            Symbol::Distr(ref d) => d.try_to_num().map(|n| format!("{}", n)).unwrap_or(d.stat_view()),
+            Symbol::Prob(p) => format!("{:?}", p),
+            Symbol::Distr(ref d) => d.try_to_num().map(|n| format!("{}", n)).unwrap_or_else(|_| d.to_string()),
             Symbol::Fn(ref fn_val) => fn_val.repr(),
+            Symbol::Lambda { ref param, ref param_type, ref body } => format!("\\{}{} -> {}", param, annotation_repr(param_type), body.repr()),
+            Symbol::Let { ref name, ref def_type, ref value, ref body } => format!("let {}{} = {} in {}", name, annotation_repr(def_type), value.repr(), body.repr()),
             Symbol::Seq(ref v) => format!("[{}]", v.iter().map(Symbol::repr).collect::<Vec<String>>().join(", ")),
             Symbol::Apply { ref target, ref args } => format!("({} >> {})", args.iter().map(Symbol::repr).collect::<Vec<String>>().join(" >> "), target.repr()),
-            Symbol::Assigner { ref name, ref def_type, ref expr } => {
-                match def_type {
-                    None => format!("{} = {}", name, expr.repr()),
-                    Some(type_) => format!("{}: {} = {}", name, type_, expr.repr()),
-                }
-            },
+            Symbol::Assigner { ref name, ref def_type, ref expr } => format!("{}{} = {}", name, annotation_repr(def_type), expr.repr()),
         }
     }
     pub fn walk(&self, env: &Env, indent_level: usize) {
         let indent: &String = &(0..indent_level).map(|_| ' ').collect();
         match *self {
             Symbol::Nil => println!("{}Nil", indent),
-            Symbol::Text(ref text) => {
-                println!("{}Text {} := ", indent, text);
-                if let Some((ref symbol, _)) = env.lookup_var(text) {
+            Symbol::Ident(ref text) => {
+                println!("{}Ident {} := ", indent, text);
+                if let Some((symbol, _)) = env.lookup_var(text) {
                     symbol.walk(env, indent_level + 4);
                 }
             },
+            Symbol::Str(ref s) => println!("{}Str: {:?}", indent, s),
             Symbol::Num(num) => println!("{}Num: {}", indent, num),
+            Symbol::Prob(p) => println!("{}Prob: {:?}", indent, p),
             Symbol::Distr(ref distr) => println!("{}Distr{}", indent, distr.stat_view()),
             Symbol::Fn(FnVal{ ref exprs , .. }) => {
                 println!("{}{}, captured: ", indent, self.repr());
@@ -84,6 +107,16 @@ impl Symbol {
                 }
                 println!("{}]", indent);
             },
+            Symbol::Lambda {ref param, ref param_type, ref body} => {
+                println!("{}Lambda \\{}{} ->", indent, param, annotation_repr(param_type));
+                body.walk(env, indent_level + 4);
+            }
+            Symbol::Let {ref name, ref def_type, ref value, ref body} => {
+                println!("{}Let {}{} =", indent, name, annotation_repr(def_type));
+                value.walk(env, indent_level + 4);
+                println!("{} in", indent);
+                body.walk(env, indent_level + 4);
+            }
             Symbol::Seq(ref v) => {
                 println!("{}Seq: [", indent);
                 for symbol in v {
@@ -106,45 +139,144 @@ impl Symbol {
             }
         }
     }
-    pub fn type_check(&self, env: &Env) -> Result<Type, Error> {
+    /// apply this function (a builtin or a lambda) to `args`
+    /// too few arguments will curry the function, and too many will be applied to its output
+    pub fn call(&self, args: &[Symbol], env: &mut Env) -> Result<Symbol, Error> {
+        match *self {
+            Symbol::Fn(ref fn_val) => {
+                let arity = fn_val.type_.in_types.len();
+                if args.len() <= arity {
+                    return fn_val.apply(args, env);
+                }
+                fn_val.apply(&args[..arity], env)?.call(&args[arity..], env)
+            }
+            Symbol::Lambda {ref param, ref param_type, ref body} => {
+                let (first, rest) = match args.split_first() {
+                    Some(split) => split,
+                    None => return Ok(self.clone()),
+                };
+                let value = first.eval(env)?.into_owned();
+                let type_ = annotated_value(param_type, &value, env, param)?;
+                // the body only sees its parameter and the globals: anything else was captured when the lambda was made
+                let result = env.with_scope(Frame::Call, param.clone(), value.clone(), type_, |env| {
+                    body.eval(env).map(Cow::into_owned)
+                })?.close_over(param, &value);
+                if rest.is_empty() {
+                    Ok(result)
+                } else {
+                    result.call(rest, env)
+                }
+            }
+            _ => Err(fail!("not a function: {}", self.repr())),
+        }
+    }
+    /// replace each free occurrence of `name` with `value`
+    pub fn substitute(&self, name: &str, value: &Symbol) -> Symbol {
+        match *self {
+            Symbol::Ident(ref text) if text == name => value.clone(),
+            Symbol::Nil | Symbol::Ident(_) | Symbol::Str(_) | Symbol::Num(_) | Symbol::Prob(_) | Symbol::Distr(_) => self.clone(),
+            Symbol::Seq(ref v) => Symbol::Seq(v.iter().map(|symbol| symbol.substitute(name, value)).collect()),
+            Symbol::Fn(ref fn_val) => FnVal {
+                ptr: fn_val.ptr,
+                type_: fn_val.type_.clone(),
+                exprs: fn_val.exprs.iter().map(|symbol| symbol.substitute(name, value)).collect(),
+            }.into(),
+            // the inner parameter shadows the outer one
+            Symbol::Lambda {ref param, ..} if param == name => self.clone(),
+            Symbol::Lambda {ref param, ref param_type, ref body} => Symbol::Lambda {
+                param: param.clone(),
+                param_type: param_type.clone(),
+                body: body.substitute(name, value).into_boxed(),
+            },
+            Symbol::Let {name: ref bound, ref def_type, value: ref bound_value, ref body} => Symbol::Let {
+                name: bound.clone(),
+                def_type: def_type.clone(),
+                value: bound_value.substitute(name, value).into_boxed(),
+                // the let shadows `name` within its body
+                body: if bound == name { body.clone() } else { body.substitute(name, value).into_boxed() },
+            },
+            Symbol::Apply {ref target, ref args} => Symbol::Apply {
+                target: target.substitute(name, value).into_boxed(),
+                args: args.iter().map(|symbol| symbol.substitute(name, value)).collect(),
+            },
+            Symbol::Assigner {name: ref assigned, ref def_type, ref expr} => Symbol::Assigner {
+                name: assigned.clone(),
+                def_type: def_type.clone(),
+                expr: expr.substitute(name, value).into_boxed(),
+            },
+        }
+    }
+    /// bind `name` to `value` inside a value that outlives the scope `name` was bound in,
+    /// i.e. the lambda returned by `let x = 1 in \\y -> x + y`
+    pub fn close_over(self, name: &str, value: &Symbol) -> Symbol {
+        match self {
+            Symbol::Lambda {..} | Symbol::Fn(_) | Symbol::Seq(_) | Symbol::Apply {..} => self.substitute(name, value),
+            _ => self,
+        }
+    }
+    pub fn type_check(&self, env: &mut Env) -> Result<Type, Error> {
         match *self {
             Symbol::Nil => Ok(Type::Nil),
             Symbol::Num(_) => Ok(Type::Num),
+            Symbol::Str(_) => Ok(Type::Str),
+            Symbol::Prob(_) => Ok(Type::Prob),
             Symbol::Distr(_) => Ok(Type::Distr),
             Symbol::Fn(FnVal{ ref type_, .. }) => Ok(type_.clone().into()),
             Symbol::Seq(ref v) => {
+                // the items' common type, or a type variable for an empty sequence, which fits wherever a sequence does
+                let mut item_type: Option<Type> = None;
                 for symbol in v {
-                    let _ = symbol.type_check(env)?;
+                    let found_type = symbol.type_check(env)?;
+                    item_type = Some(item_type.map_or(found_type.clone(), |item_type| item_type.join(&found_type)));
                 }
-                //todo type inference that is wicked smaht and can handle zero sized sequences
-                Ok(Type::Seq(Box::new(Type::Any)))
+                Ok(Type::Seq(Box::new(item_type.unwrap_or_else(|| Type::Var("a".to_string()).instantiate()))))
             }
             Symbol::Apply {ref target, ref args} => {
-                let type_ = target.type_check(env)?;
-                if type_.is_any() { return Ok(Type::Any); } // Any type skips type checking until evaluation
-                if let Type::Fn(fn_type) = type_ {
-                    if args.len() > fn_type.in_types.len() {
-                        return Err(fail!("too many arguments applied to function ({} expected {}, gave it {})", target.repr(), fn_type.in_types.len(), args.len()))
-                    }
+                // what the type variables of a generic function stand for in this application
+                let mut bindings = Bindings::new();
+                let mut type_ = target.type_check(env)?.instantiate();
+                let mut args = args.as_slice();
+                loop {
+                    // Any type, or a type variable nothing has pinned down, skips type checking until evaluation
+                    if type_.is_any() || matches!(type_, Type::Var(_)) { return Ok(Type::Any); }
+                    let fn_type = match type_ {
+                        Type::Fn(fn_type) => fn_type,
+                        _ => return Err(fail!("not a function: {}, found type {}", target.repr(), type_.readable())),
+                    };
                     // each type in our argument much be coercible to the corresponding in_type in the signature
                     for (i, (arg, expected_type)) in args.iter().zip(fn_type.in_types.iter()).enumerate() {
-                        let found_type = arg.type_check(env)?;
-                        if !found_type.coercible_to(expected_type) {
-                            return Err(fail!("incorrect signature for function `{}` at position {}: expected type {}, found type {}", target.repr(), i, expected_type, found_type))
+                        let found_type = arg.type_check(env)?.instantiate();
+                        if !expected_type.unify(&found_type, &mut bindings) {
+                            let shown = Type::readable_all(&[&expected_type.resolve(&bindings), &found_type.resolve(&bindings)]);
+                            return Err(fail!("incorrect signature for function `{}` at position {}: expected type {}, found type {}", target.repr(), i, shown[0], shown[1]))
                         }
                     }
                     if args.len() < fn_type.in_types.len() {
                         // more to do: the function will be curried
-                        Ok(fn_type.curry(args.len()).into())
-                    } else {
-                        // exact match: the underlying function pointer will be evoked
-                        Ok(fn_type.out_type.as_ref().clone())
+                        return Ok(Type::from(fn_type.curry(args.len())).resolve(&bindings));
+                    }
+                    // the underlying function will be evoked, and any remaining arguments are applied to its output
+                    args = &args[fn_type.in_types.len()..];
+                    type_ = fn_type.out_type.resolve(&bindings);
+                    if args.is_empty() {
+                        return Ok(type_);
+                    }
+                    if type_.try_to_fn().is_none() && !type_.is_any() && !matches!(type_, Type::Var(_)) {
+                        return Err(fail!("too many arguments applied to function ({} returns {}, which can not be applied to {} more)", target.repr(), type_.readable(), args.len()))
                     }
-                } else {
-                    return Err(fail!("not a function: {}, found type {}", target.repr(), type_));
                 }
             },
-            Symbol::Text(ref name) => {
+            Symbol::Lambda {ref param, ref param_type, ref body} => {
+                let in_type = param_type.clone().unwrap_or(Type::Any);
+                let out_type = env.with_scope(Frame::Let, param.clone(), Symbol::Nil, in_type.clone(), |env| body.type_check(env))?;
+                Ok(FnType { in_types: vec![in_type], out_type: Box::new(out_type) }.into())
+            }
+            Symbol::Let {ref name, ref def_type, ref value, ref body} => {
+                let value_type = annotated(def_type, type_check_as(value, def_type, env)?, name)?;
+                // the placeholder is never evaluated, only its type is looked up
+                env.with_scope(Frame::Let, name.clone(), Symbol::Nil, value_type, |env| body.type_check(env))
+            }
+            Symbol::Ident(ref name) => {
                 if let Some((_, type_)) = env.lookup_var(name) {
                     // ignore the symbol: may be a placeholder
                     Ok(type_.clone())
@@ -152,54 +284,117 @@ impl Symbol {
                     Err(fail!("{:?} has no binding in current namespace", name))
                 }
             }
-            Symbol::Assigner {name: _, ref def_type, ref expr} => {
+            Symbol::Assigner {ref name, ref def_type, ref expr} => {
                 //TODO typecheck with arguments ??
-                let concrete_type = expr.type_check(env)?;
-                if let Some(_type) = def_type {
-                    if !concrete_type.coercible_to(&Type::try_from(_type).ok_or(fail!("invalid type: {}", _type))? ) {
-                        return Err(fail!("annotated type {:?} does not match concrete type {:?}", _type, concrete_type));
-                    }
-                }
+                annotated(def_type, type_check_as(expr, def_type, env)?, name)?;
                 Ok(Type::Nil)
             }
         }
     }
-    pub fn eval(&self, env: &mut Env) -> Result<Cow<Symbol>, Error> {
+    pub fn eval(&self, env: &mut Env) -> Result<Cow<'_, Symbol>, Error> {
         Ok(match self {
-            Symbol::Nil | Symbol::Num(_) | Symbol::Distr(_) | Symbol::Fn(_) => Cow::Borrowed(self),
+            Symbol::Nil | Symbol::Str(_) | Symbol::Num(_) | Symbol::Prob(_) | Symbol::Distr(_) | Symbol::Fn(_) => Cow::Borrowed(self),
+            Symbol::Lambda{..} => {
+                // capture the `let`s and parameters around the lambda, which are gone by the time it is called
+                let locals = env.locals();
+                if locals.is_empty() {
+                    Cow::Borrowed(self)
+                } else {
+                    Cow::Owned(locals.into_iter().fold(self.clone(), |lambda, (name, value)| lambda.substitute(name, value)))
+                }
+            }
+            Symbol::Let {ref name, ref def_type, ref value, ref body} => {
+                let value = value.eval(env)?.into_owned();
+                let type_ = annotated_value(def_type, &value, env, name)?;
+                let result = env.with_scope(Frame::Let, name.clone(), value.clone(), type_, |env| {
+                    body.eval(env).map(Cow::into_owned)
+                })?;
+                Cow::Owned(result.close_over(name, &value))
+            }
             Symbol::Seq(ref v) => {
                 // evaluate each item and put it back in a sequence
                 Cow::Owned(Symbol::Seq(v.iter().map(|expr| expr.eval(env).map(Cow::into_owned)).collect::<Result<Vec<Symbol>, Error>>()?))
             }
             Symbol::Apply {ref target, ref args} => Cow::Owned({
                 let eval_func = target.eval(env)?;
-                if let Symbol::Fn(fn_val) = eval_func.as_ref() {
-                    fn_val.apply(args, env)?
-                } else {
-                    return Err(fail!("not a function: {}", eval_func.repr()))
-                }
+                eval_func.call(args, env)?
             }),
-            Symbol::Text(ref name) => {
-                if let Some(value) = env.lookup_var(name).map(|(x, _)| x.clone()) {
-                    Cow::Owned(value)
-                } else {
-                    Cow::Borrowed(self)
+            Symbol::Ident(ref name) => {
+                match env.lookup_var(name) {
+                    Some((value, _)) => Cow::Owned(match name.rsplit_once('.') {
+                        Some((module, _)) => env.qualify(module, value.clone()),
+                        None => value.clone(),
+                    }),
+                    None => return Err(fail!("{:?} has no binding in current namespace", name)),
                 }
             },
-            Symbol::Assigner {ref name, def_type: _, ref expr} => {
+            Symbol::Assigner {ref name, ref def_type, ref expr} => {
                 let value = expr.eval(env)?;
-                let type_ = value.type_check(env)?;
+                let type_ = annotated_value(def_type, &value, env, name)?;
                 env.bind_var(name.clone(), value.into_owned(), type_);
                 Cow::Owned(Symbol::Nil)
             }
         })
     }
 }
+/// the type a name is bound with: its annotation if it has one, as long as the value found fits it
+fn annotated(def_type: &Option<Type>, found: Type, name: &str) -> Result<Type, Error> {
+    match def_type {
+        Some(expected) if !expected.admits(&found) => {
+            let shown = Type::readable_all(&[expected, &found]);
+            Err(fail!("{} is annotated with type {}, but found type {}", name, shown[0], shown[1]))
+        }
+        Some(expected) => Ok(expected.clone()),
+        None => Ok(found),
+    }
+}
+
+/// the type of `expr`, where an unannotated lambda takes its parameter types from the annotation `def_type`,
+/// so that `\x -> x` is checked as an `Fn(a) -> a` rather than as the `Fn(Any) -> Any` it would fit any annotation as
+fn type_check_as(expr: &Symbol, def_type: &Option<Type>, env: &mut Env) -> Result<Type, Error> {
+    match (expr, def_type) {
+        (Symbol::Lambda {param, param_type: None, body}, Some(Type::Fn(fn_type))) if !fn_type.in_types.is_empty() => {
+            let in_type = fn_type.in_types[0].clone();
+            let out_type = match fn_type.in_types.len() {
+                1 => *fn_type.out_type.clone(),
+                _ => fn_type.curry(1).into(),
+            };
+            let out_type = env.with_scope(Frame::Let, param.clone(), Symbol::Nil, in_type.clone(), |env| {
+                type_check_as(body, &Some(out_type), env)
+            })?;
+            Ok(FnType { in_types: vec![in_type], out_type: Box::new(out_type) }.into())
+        }
+        _ => expr.type_check(env),
+    }
+}
+
+/// as `annotated`, for a value that has been evaluated: a sequence mixing types is only a `Seq<Any>`,
+/// which fits any sequence annotation, so its items are checked one by one
+fn annotated_value(def_type: &Option<Type>, value: &Symbol, env: &mut Env, name: &str) -> Result<Type, Error> {
+    let type_ = annotated(def_type, type_check_as(value, def_type, env)?, name)?;
+    if let (Some(Type::Seq(item_type)), Symbol::Seq(items)) = (def_type, value) {
+        let item_type = Some((**item_type).clone());
+        for item in items {
+            annotated_value(&item_type, item, env, name)?;
+        }
+    }
+    Ok(type_)
+}
+
+fn annotation_repr(def_type: &Option<Type>) -> String {
+    def_type.as_ref().map_or(String::new(), |type_| format!(": {}", type_))
+}
+
 impl std::convert::From<KeyType> for Symbol {
     fn from(n: KeyType) -> Symbol {
         Symbol::Num(n)
     }
 }
+impl std::convert::From<ProbType> for Symbol {
+    fn from(p: ProbType) -> Symbol {
+        Symbol::Prob(p)
+    }
+}
 impl std::convert::From<Distr> for Symbol {
     fn from(distr: Distr) -> Symbol {
         Symbol::Distr(distr)
@@ -207,7 +402,7 @@ impl std::convert::From<Distr> for Symbol {
 }
 impl std::convert::From<String> for Symbol {
     fn from(s: String) -> Symbol {
-        Symbol::Text(s)
+        Symbol::Ident(s)
     }
 }
 impl std::convert::From<()> for Symbol {
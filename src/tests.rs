@@ -1,38 +1,796 @@
 use crate::*;
 use crate::type_info::*;
-use parse_analyze_evaluate;
 
 #[test]
 fn test_add() {
     let mut env = Env::new();
+    env.import_arithmetic();
     let expr = parse_analyze_evaluate("10 + 2", &mut env).unwrap();
-    assert_eq!(expr.try_to_num().into_owned(), 12);
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 12);
 }
 #[test]
 fn test_parens() {
     let mut env = Env::new();
     let expr = parse_analyze_evaluate("(10)", &mut env).unwrap();
-    assert_eq!(expr.try_to_num().into_owned(), 10);
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 10);
 }
 #[test]
 fn test_embed_parens() {
     let mut env = Env::new();
+    env.import_arithmetic();
     let expr = parse_analyze_evaluate("(10 + 5) * 2", &mut env).unwrap();
-    assert_eq!(expr.try_to_num().into_owned(), 30);
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 30);
 }
 #[test]
 fn test_var_read() {
     let mut env = Env::new();
     env.bind_var("x".to_string(), Symbol::Num(5), Type::Num);
     let expr = parse_analyze_evaluate("x", &mut env).unwrap();
-    assert_eq!(expr.try_to_num().into_owned(), 5);
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 5);
 }
 #[test]
 fn test_var_assign() {
     use env::Env;
     let mut env = Env::new();
-    let expr = parse_analyze_evaluate("x = 2", &mut env).unwrap();
+    let _ = parse_analyze_evaluate("x = 2", &mut env).unwrap();
     assert!(env.lookup_var("x").is_some());
-    let num: i32 = env.lookup_var("x").unwrap().0.try_to_num().into_owned();
+    let num: i32 = env.lookup_var("x").unwrap().0.try_to_num().unwrap().into_owned();
     assert_eq!(num, 2i32)
-}
\ No newline at end of file
+}
+#[test]
+fn test_compound_dice() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("(1d2)d2", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(1) - 0.25).abs() < 1e-9);
+    assert!((distr.prob(2) - 0.375).abs() < 1e-9);
+    assert!((distr.prob(3) - 0.25).abs() < 1e-9);
+    assert!((distr.prob(4) - 0.125).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("(d4)d6 + 1", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().mean() - 2.5 * 3.5 - 1.0).abs() < 1e-9);
+    // a bracketed argument followed by a die is two arguments, not compound dice
+    let expr = parse_analyze_evaluate("add (d4) d6", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().mean() - 2.5 - 3.5).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("add (d4 + 1) d6", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().mean() - 3.5 - 3.5).abs() < 1e-9);
+    // a negative number of dice is an error, whether or not it is certain
+    for line in ["(d4 - 2)d6", "(-1)d6", "roll (-1)d6"] {
+        let err = parse_analyze_evaluate(line, &mut env).unwrap_err();
+        assert!(err.reason.contains("can not roll a negative number of dice"), "{}: {}", line, err.reason);
+    }
+}
+#[test]
+fn test_custom_dice() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("4dF", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(4) - 1.0 / 81.0).abs() < 1e-9);
+    assert!((distr.prob(0) - 19.0 / 81.0).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("d{1, 1, 2} + (1)d{-1,5}", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(0) - 1.0 / 3.0).abs() < 1e-9);
+    assert!((distr.prob(7) - 1.0 / 6.0).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("make-custom-dice 2 [0, 1]", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().prob(1) - 0.5).abs() < 1e-9);
+}
+#[test]
+fn test_success_pool() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("2d10>=8", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(0) - 0.49).abs() < 1e-9);
+    assert!((distr.prob(1) - 0.42).abs() < 1e-9);
+    assert!((distr.prob(2) - 0.09).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("1d10>=8 double 10 subtract 1", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(-1) - 0.1).abs() < 1e-9);
+    assert!((distr.prob(0) - 0.6).abs() < 1e-9);
+    assert!((distr.prob(1) - 0.2).abs() < 1e-9);
+    assert!((distr.prob(2) - 0.1).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("pool 3 d6 5", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().mean() - 1.0).abs() < 1e-9);
+    let err = parse_analyze_evaluate("d0>=1", &mut env).unwrap_err();
+    assert!(err.reason.contains("a die needs at least one face"), "{}", err.reason);
+    // a pool counts every die, so it can not keep only some of them
+    let err = parse_analyze_evaluate("4d6kh3>=5", &mut env).unwrap_err();
+    assert!(err.reason.contains("can not keep the highest or lowest"), "{}", err.reason);
+}
+#[test]
+fn test_distr_literal() {
+    let mut env = Env::new();
+    env.import_arithmetic();
+    let expr = parse_analyze_evaluate("{1: 0.25, 2: 0.5, 3: .25} + 1", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(3) - 0.5).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("{1: 0.5, 2: 0.4}", &mut env).is_err());
+    assert!(parse_analyze_evaluate("{1: 1.5, 2: -0.5}", &mut env).is_err());
+}
+#[test]
+fn test_distr_round_trip() {
+    let mut env = Env::new();
+    let original = distr::Distr::stacked_unifs(3, 7).combine_op(&distr::Distr::fate(), |x, y| x * y);
+    let expr = parse_analyze_evaluate(&original.to_string(), &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), original);
+}
+#[test]
+fn test_given() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("d20 given != 1", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert_eq!(distr.prob(1), 0.0);
+    assert!((distr.prob(2) - 1.0 / 19.0).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("2d6 + 1 where >= 12", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.prob(12) - 2.0 / 3.0).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("d6 given > 6", &mut env).is_err());
+}
+#[test]
+fn test_truncation() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("min-at d20 10", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().prob(10) - 0.5).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("clamp d20 5 15", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert_eq!(distr.len(), 11);
+    assert!((distr.prob(15) - 0.3).abs() < 1e-9);
+}
+#[test]
+fn test_lambda() {
+    let mut env = Env::new();
+    env.import_arithmetic();
+    let expr = parse_analyze_evaluate("(\\x -> x * 2 + 1) 4", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 9);
+    let expr = parse_analyze_evaluate("(\\x -> \\y -> x - y) 10 3", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 7);
+}
+#[test]
+fn test_map_outcomes() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("map-outcomes (\\x -> x / 2) 2d6", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert_eq!(distr.iter().cloned().collect::<Vec<i32>>(), vec![1, 2, 3, 4, 5, 6]);
+    assert!((distr.prob(1) - 3.0 / 36.0).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("combine sub d6 d6", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().prob(0) - 1.0 / 6.0).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("map-outcomes (\\x -> d4) d6", &mut env).is_err());
+}
+#[test]
+fn test_mix() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("mix [[0.6, 2d6], [0.4, 10]]", &mut env).unwrap();
+    let distr = expr.try_to_distr().unwrap();
+    assert!((distr.mean() - (0.6 * 7.0 + 0.4 * 10.0)).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("either .25 1 0", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().prob(1) - 0.25).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("mix [[0.6, 2d6], [0.6, 10]]", &mut env).is_err());
+    assert!(parse_analyze_evaluate("either 1.5 1 0", &mut env).is_err());
+}
+#[test]
+fn test_opposed_checks() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("beats d20 d20", &mut env).unwrap();
+    assert!((expr.try_to_prob().unwrap() - 0.475).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("ties d20 d20", &mut env).unwrap();
+    assert!((expr.try_to_prob().unwrap() - 0.05).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("contest (d20 + 2) d20 \"defender\"", &mut env).unwrap();
+    let odds = expr.try_to_seq().unwrap();
+    assert!((odds[0].try_to_prob().unwrap() - 0.5725).abs() < 1e-9);
+    assert!((odds[1].try_to_prob().unwrap() - 0.4275).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("contest (d20 + 2) d20 \"attacker\"", &mut env).unwrap();
+    let odds = expr.try_to_seq().unwrap();
+    assert!((odds[0].try_to_prob().unwrap() - 0.6175).abs() < 1e-9);
+    assert!((odds[1].try_to_prob().unwrap() - 0.3825).abs() < 1e-9);
+    let expr = parse_analyze_evaluate("contest d20 d20 \"reroll\"", &mut env).unwrap();
+    let odds = expr.try_to_seq().unwrap();
+    assert!((odds[0].try_to_prob().unwrap() - 0.5).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("contest d20 d20 \"nobody\"", &mut env).is_err());
+    assert!(parse_analyze_evaluate("contest 3 3 \"reroll\"", &mut env).is_err());
+}
+#[test]
+fn test_comparison_metrics() {
+    use distr::Distr;
+    let d6 = Distr::unif(6);
+    let d8 = Distr::unif(8);
+    assert!((d6.stdev() - (35.0f64 / 12.0).sqrt()).abs() < 1e-9);
+    assert!((d6.total_variation(&d8) - 0.25).abs() < 1e-9);
+    assert!((d6.overlap(&d8) - 0.75).abs() < 1e-9);
+    assert!((d8.kl_divergence(&d6)).is_infinite());
+    assert!((d6.kl_divergence(&d8) - (8.0f64 / 6.0).ln()).abs() < 1e-9);
+    assert!(d8.dominates(&d6));
+    assert!(!d6.dominates(&d8));
+    assert!(!d6.dominates(&d6));
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("dominates (d6 + 1) d6", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 1);
+}
+#[test]
+fn test_sweep() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("sweep (\\ac -> d20 + 5 given >= ac) [10..26] [10, 50, 90]", &mut env);
+    assert!(expr.is_err()); // d20 + 5 can never reach 26
+    let expr = parse_analyze_evaluate("sweep (\\ac -> d20 + 5 given >= ac) [10..20] [10, 50, 90]", &mut env).unwrap();
+    assert!(expr.try_to_num().is_err());
+    let expr = parse_analyze_evaluate("sweep-grid (\\x -> \\y -> make-dice x y) [1..3] [4, 6]", &mut env);
+    assert!(expr.is_ok());
+    let distr = distr::Distr::unif(20);
+    assert_eq!(distr.percentile(0.5), Some(10));
+    assert_eq!(distr.percentile(0.51), Some(11));
+    assert_eq!(distr.percentile(1.0), Some(20));
+    // one row per parameter, with the mean, stdev and each percentile of d20 + x
+    let rows = [0, 5].iter().map(|x| (*x, distr.map_op(|y| y + x))).collect::<Vec<_>>();
+    let view = distr::Distr::sweep_view(&rows, &[10, 50, 90]);
+    let lines = view.lines().collect::<Vec<&str>>();
+    assert_eq!(lines[0], "     x |     mean |    stdev |   p10 |   p50 |   p90");
+    assert_eq!(lines[2], "     0 |   10.500 |    5.766 |     2 |    10 |    18");
+    assert_eq!(lines[3], "     5 |   15.500 |    5.766 |     7 |    15 |    23");
+    // one row per x and one column per y, each cell the mean of xdy
+    let cells = [1, 3].iter()
+        .map(|x| [4, 6].iter().map(|y| distr::Distr::stacked_unifs(*x, *y)).collect())
+        .collect::<Vec<Vec<distr::Distr>>>();
+    let view = distr::Distr::grid_view(&[1, 3], &[4, 6], &cells);
+    let lines = view.lines().collect::<Vec<&str>>();
+    assert_eq!(lines[0], " x \\ y |        4 |        6");
+    assert_eq!(lines[2], "     1 |    2.500 |    3.500");
+    assert_eq!(lines[3], "     3 |    7.500 |   10.500");
+}
+#[test]
+fn test_multi_hist() {
+    use distr::Distr;
+    let (d4, d6) = (Distr::unif(4), Distr::unif(6));
+    let view = Distr::multi_hist_view(&[("#1".to_string(), &d4), ("#2".to_string(), &d6)], 53);
+    assert_eq!(view.lines().count(), 6 * 2 + 2);
+    assert!(view.contains("X = #1"));
+    assert!(view.contains("O = #2"));
+    // both are drawn against the tallest bar
+    assert!(view.lines().next().unwrap().ends_with(&"X".repeat(50)));
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("hist [[\"longsword\", d8 + 3], [\"greatsword\", 2d6 + 3]]", &mut env).unwrap();
+    assert!(expr.try_to_seq().is_err());
+    parse_analyze_evaluate("hist [d4, d6]", &mut env).unwrap();
+    parse_analyze_evaluate("hist [d20]", &mut env).unwrap();
+    // the argument must be a sequence, and a label must be a string
+    let err = parse_analyze_evaluate("hist d20", &mut env).unwrap_err();
+    assert!(err.reason.contains("type checker failed"), "{}", err.reason);
+    assert!(parse_analyze_evaluate("hist [[1, d6]]", &mut env).is_err());
+}
+#[test]
+fn test_render_histogram() {
+    use distr::Distr;
+    use render::{HistOptions, Orientation, Scale};
+    let distr = Distr::stacked_unifs(3, 6);
+    let options = HistOptions { width: 40, ..HistOptions::default() };
+    let view = render::histogram(&distr, &options);
+    assert_eq!(view.lines().count(), 16);
+    assert!(view.lines().all(|line| line.chars().count() <= 40));
+    // three digit outcomes are labelled in full, and a wide support is binned
+    let wide = Distr::stacked_unifs(40, 6);
+    let view = render::histogram(&wide, &HistOptions { max_bins: 30, ..options.clone() });
+    assert!(view.lines().count() <= 30);
+    assert!(view.contains("..240 "));
+    // on a log scale even the least likely outcome gets a bar
+    let view = render::histogram(&wide, &HistOptions { scale: Scale::Log, ..options.clone() });
+    assert!(view.lines().all(|line| line.contains(|c| ('█'..='▏').contains(&c))));
+    let view = render::histogram(&distr, &HistOptions { orientation: Orientation::Vertical, height: 8, ..options });
+    assert_eq!(view.lines().count(), 10);
+}
+#[test]
+fn test_export_svg() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let path = std::env::temp_dir().join("dnd-calculator-test-export.svg");
+    let line = format!("export-svg {:?} \"d8 < 2d4\" [d8, 2d4]", path.to_str().unwrap());
+    parse_analyze_evaluate(&line, &mut env).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(contents.starts_with("<svg"));
+    assert!(contents.contains("d8 &lt; 2d4"));
+    assert!(contents.contains("mean 4.500"));
+    assert!(contents.contains("mean 5.000"));
+    assert_eq!(contents.matches("stroke-dasharray").count(), 2);
+}
+#[test]
+fn test_csv_round_trip() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let path = std::env::temp_dir().join("dnd-calculator-test-round-trip.csv");
+    let path = path.to_str().unwrap();
+    parse_analyze_evaluate(&format!("save-csv {:?} (3d6 - 2dF)", path), &mut env).unwrap();
+    let loaded = parse_analyze_evaluate(&format!("load-csv {:?}", path), &mut env).unwrap();
+    let _ = std::fs::remove_file(path);
+    let original = distr::Distr::stacked_unifs(3, 6).combine_op(&distr::Distr::stacked(2, &distr::Distr::fate()), |x, y| x - y);
+    assert_eq!(*loaded.try_to_distr().unwrap(), original);
+    assert!(distr::Distr::from_csv("outcome,probability\n1,0.5\n2,0.25\n").is_err());
+}
+#[cfg(feature = "serde")]
+#[test]
+fn test_json_round_trip() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let path = std::env::temp_dir().join("dnd-calculator-test-round-trip.json");
+    let path = path.to_str().unwrap();
+    parse_analyze_evaluate(&format!("save-json {:?} (d20 given >= 2)", path), &mut env).unwrap();
+    let loaded = parse_analyze_evaluate(&format!("load-json {:?}", path), &mut env).unwrap();
+    let _ = std::fs::remove_file(path);
+    let original = distr::Distr::unif(20).given(|x| x >= 2).unwrap();
+    assert_eq!(*loaded.try_to_distr().unwrap(), original);
+    assert!(distr::Distr::from_json("{\"1\": 0.5}").is_err());
+}
+#[test]
+fn test_fairness() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let path = std::env::temp_dir().join("dnd-calculator-test-rolls.txt");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "# a perfectly even d6\n1 2 3 4 5 6\n6,5,4,3,2,1\n").unwrap();
+    let loaded = parse_analyze_evaluate(&format!("load-rolls {:?}", path), &mut env).unwrap();
+    let fair = parse_analyze_evaluate(&format!("fairness {:?} d6", path), &mut env).unwrap();
+    let _ = std::fs::remove_file(path);
+    assert_eq!(*loaded.try_to_distr().unwrap(), distr::Distr::unif(6));
+    for p in fair.try_to_seq().unwrap() {
+        assert!((p.try_to_prob().unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    // 60 rolls expect 10 of each face, so 15 ones and 5 twos give a chi-square of (25 + 25) / 10 = 5,
+    // which on 5 degrees of freedom has a p-value of about 0.416
+    let rolls = [vec![1; 15], vec![2; 5], vec![3; 10], vec![4; 10], vec![5; 10], vec![6; 10]].concat();
+    let rolls = rolls.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ");
+    let p = parse_analyze_evaluate(&format!("fairness [{}] d6", rolls), &mut env).unwrap();
+    let chi_square = stats::chi_square(&stats::parse_rolls(&rolls).unwrap(), &distr::Distr::unif(6)).unwrap();
+    assert!((chi_square.statistic - 5.0).abs() < 1e-9);
+    assert!((p.try_to_seq().unwrap()[0].try_to_prob().unwrap() - 0.41588).abs() < 1e-4);
+
+    let loaded = parse_analyze_evaluate("fairness [20, 20, 20, 20, 20, 20, 20, 20, 20, 20] d20", &mut env).unwrap();
+    for p in loaded.try_to_seq().unwrap() {
+        assert!(p.try_to_prob().unwrap() < 0.01);
+    }
+    assert!(parse_analyze_evaluate("fairness [0, 1] d6", &mut env).unwrap().try_to_seq().unwrap()[0].try_to_prob().unwrap() == 0.0);
+}
+#[test]
+fn test_sampling() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    // small expressions are still computed exactly
+    let exact = parse_analyze_evaluate("2d6 + 1 sampled 1000 seed 3", &mut env).unwrap();
+    assert_eq!(*exact.try_to_distr().unwrap(), distr::Distr::stacked_unifs(2, 6).map_op(|x| x + 1));
+
+    let first = parse_analyze_evaluate("20d20 * 20d20 sampled 2000 seed 7", &mut env).unwrap();
+    let again = parse_analyze_evaluate("20d20 * 20d20 sampled 2000 seed 7", &mut env).unwrap();
+    let other = parse_analyze_evaluate("20d20 * 20d20 sampled 2000 seed 8", &mut env).unwrap();
+    let first = first.try_to_distr().unwrap().into_owned();
+    assert_eq!(first, *again.try_to_distr().unwrap());
+    assert_ne!(first, *other.try_to_distr().unwrap());
+    assert!(first.len() <= 2000);
+    // the exact mean is 210 * 210, and the sampled one should land within a few standard errors
+    assert!((first.mean() - 44100.0).abs() < 4.0 * first.stdev() / 2000f64.sqrt());
+    // a lone dice literal is sampled too, rather than convolved exactly while parsing
+    let dice = parse_analyze_evaluate("200d100 sampled 1000 seed 1", &mut env).unwrap();
+    let dice = dice.try_to_distr().unwrap();
+    // the exact result would have 19801 outcomes
+    assert!(dice.len() <= 1000);
+    assert!((dice.mean() - 10100.0).abs() < 4.0 * dice.stdev() / 1000f64.sqrt());
+
+    parse_analyze_evaluate("sampling 500 1", &mut env).unwrap();
+    let session = parse_analyze_evaluate("(4d6)d100", &mut env).unwrap();
+    assert!((session.try_to_distr().unwrap().iter().count()) <= 500);
+    parse_analyze_evaluate("sampling 0 0", &mut env).unwrap();
+    let exact = parse_analyze_evaluate("(1d2)d4", &mut env).unwrap();
+    assert!((exact.try_to_distr().unwrap().mean() - 3.75).abs() < 1e-9);
+    assert!(parse_analyze_evaluate("d6 sampled 0", &mut env).is_err());
+    // and a seed always samples the same outcomes, on any platform
+    let draws = sampling::Sampler::new(10, 7).draws(&distr::Distr::unif(20), 5);
+    assert_eq!(draws, vec![1, 11, 20, 6, 4]);
+}
+#[test]
+fn test_keep_dice() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let advantage = parse_analyze_evaluate("2d20kh1", &mut env).unwrap();
+    let advantage = advantage.try_to_distr().unwrap();
+    assert!((advantage.prob(20) - 39.0 / 400.0).abs() < 1e-9);
+    assert!((advantage.prob(1) - 1.0 / 400.0).abs() < 1e-9);
+    let disadvantage = parse_analyze_evaluate("2d20kl1", &mut env).unwrap();
+    assert!((disadvantage.try_to_distr().unwrap().mean() - 7.175).abs() < 1e-9);
+    let stats = parse_analyze_evaluate("4d6kh3", &mut env).unwrap();
+    assert!((stats.try_to_distr().unwrap().mean() - 15869.0 / 1296.0).abs() < 1e-9);
+}
+#[test]
+fn test_roll() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    parse_analyze_evaluate("roll-seed 42", &mut env).unwrap();
+    let first = parse_analyze_evaluate("roll 4d6kh3 + 2", &mut env).unwrap().try_to_num().unwrap().into_owned();
+    let second = parse_analyze_evaluate("roll (d4)d6 + 10d{1,1,2}", &mut env).unwrap().try_to_num().unwrap().into_owned();
+    parse_analyze_evaluate("roll 3d10>=8", &mut env).unwrap();
+    assert!(parse_analyze_evaluate("roll d6 given > 6", &mut env).is_err());
+    assert!(parse_analyze_evaluate("roll {1: 0.5, 3: 0.5} + d4", &mut env).is_ok());
+
+    let history = env.roller().history.clone();
+    assert_eq!(history.len(), 4);
+    let stats = &history[0];
+    assert_eq!(stats.expr, "4d6kh3 + 2");
+    assert_eq!(stats.dice.len(), 1);
+    assert_eq!(stats.dice[0].label, "4d6kh3");
+    assert_eq!(stats.dice[0].faces.len(), 4);
+    assert_eq!(stats.dice[0].kept.iter().filter(|kept| **kept).count(), 3);
+    let kept_total: distr::KeyType = stats.dice[0].faces.iter().zip(stats.dice[0].kept.iter()).filter(|(_, k)| **k).map(|(x, _)| x).sum();
+    assert_eq!(first, kept_total + 2);
+    assert_eq!(history[1].dice.len(), 3);
+    assert_eq!(history[1].dice[2].label, "10d{1,1,2}");
+    // the d4 only decides how many d6 are rolled
+    assert_eq!(history[1].dice[1].faces.len() as distr::KeyType, history[1].dice[0].faces[0]);
+    assert_eq!(second, history[1].dice[1..].iter().map(|dice| dice.faces.iter().sum::<distr::KeyType>()).sum::<distr::KeyType>());
+    assert!(history[2].dice[0].faces.iter().all(|x| (1..=10).contains(x)));
+
+    // the same seed rolls the same dice
+    parse_analyze_evaluate("roll-seed 42", &mut env).unwrap();
+    let again = parse_analyze_evaluate("roll 4d6kh3 + 2", &mut env).unwrap().try_to_num().unwrap().into_owned();
+    assert_eq!(first, again);
+    assert_eq!(env.roller().history[4].dice[0].faces, stats.dice[0].faces);
+
+    let path = std::env::temp_dir().join("dnd-calculator-test-roll-log.csv");
+    parse_analyze_evaluate(&format!("export-rolls {:?}", path.to_str().unwrap()), &mut env).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(contents.lines().count(), 6);
+    assert!(contents.lines().nth(1).unwrap().starts_with("\"4d6kh3 + 2\","));
+
+    // names that only start with roll are not rolls
+    parse_analyze_evaluate("roll-history 0", &mut env).unwrap();
+    parse_analyze_evaluate("rolls = 3", &mut env).unwrap();
+    let rolls = parse_analyze_evaluate("rolls + 1", &mut env).unwrap();
+    assert_eq!(rolls.try_to_num().unwrap().into_owned(), 4);
+    assert_eq!(env.roller().history.len(), 5);
+}
+#[test]
+fn test_verifiable_rolls() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let path = std::env::temp_dir().join("dnd-calculator-test-audit.log");
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let commitment = parse_analyze_evaluate(&format!("commit-rolls {:?}", path), &mut env).unwrap();
+    assert!(parse_analyze_evaluate("roll-seed 1", &mut env).is_err());
+    // the environment is printed while debugging, and must not give the seed away before it is revealed
+    let debugged = format!("{:?}", env);
+    parse_analyze_evaluate("bonus = 5", &mut env).unwrap();
+    parse_analyze_evaluate("atk = \\n -> d20 + n", &mut env).unwrap();
+    let rolls = ["roll d20 + 5", "roll 4d6kh3", "roll d6 given > 6", "roll (d4)d8", "roll d20 +\n\t5", "roll d20 + bonus", "roll atk 3"];
+    for expr in rolls.iter() {
+        let _ = parse_analyze_evaluate(expr, &mut env);
+    }
+    let seed = parse_analyze_evaluate(&format!("reveal-rolls {:?}", path), &mut env).unwrap();
+    let seed = audit::seed_from_hex(seed.try_to_str().unwrap()).unwrap();
+    assert_eq!(audit::commitment(&seed), commitment.try_to_str().unwrap());
+    assert!(!debugged.contains(&audit::to_hex(&seed)));
+    assert!(debugged.contains(commitment.try_to_str().unwrap()));
+
+    let log = std::fs::read_to_string(path).unwrap();
+    let sessions = audit::parse_log(&log).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].entries.len(), 7);
+    assert!(matches!(&sessions[0].entries[2], audit::Entry::Failed { expr, .. } if expr == "d6 given > 6"));
+    // a roll written over several lines is still a single entry
+    assert!(matches!(&sessions[0].entries[4], audit::Entry::Roll { expr, .. } if expr == "d20 +\n\t5"));
+    // rolls that use names bound during the session are logged as rolls like any other
+    assert!(matches!(&sessions[0].entries[6], audit::Entry::Roll { draws, .. } if draws.iter().any(|(die, _)| *die == distr::Distr::unif(20))));
+    let verified = parse_analyze_evaluate(&format!("verify-rolls {:?}", path), &mut env).unwrap();
+    assert_eq!(verified.try_to_num().unwrap().into_owned(), 1);
+    // the replay does not depend on what the verifier has bound, even for rolls that used the session's own names
+    parse_analyze_evaluate("make-dice = \\k -> \\n -> 1", &mut env).unwrap();
+    let mut verifier = Env::new();
+    verifier.import_arithmetic().import_dice();
+    parse_analyze_evaluate("sampling 10 1", &mut verifier).unwrap();
+    for env in [&mut env, &mut verifier] {
+        let verified = parse_analyze_evaluate(&format!("verify-rolls {:?}", path), env).unwrap();
+        assert_eq!(verified.try_to_num().unwrap().into_owned(), 1);
+    }
+    // and a revealed seed always replays the same rolls, on any platform
+    let mut replay = rolling::Roller::replay([7; 32]);
+    let faces = (0..5).map(|_| replay.draw(&distr::Distr::unif(20)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(faces, vec![6, 9, 7, 18, 3]);
+
+    // change the die drawn by the first roll, and the replay no longer matches
+    let tampered = log.lines()
+        .map(|line| if line.starts_with("roll\t") && line.contains("\td20 + 5\t") {
+            let mut fields = line.split('\t').map(str::to_string).collect::<Vec<String>>();
+            let (distr, face) = fields[6].strip_suffix(']').unwrap().rsplit_once('[').unwrap();
+            fields[6] = format!("{}[{}]", distr, face.parse::<i32>().unwrap() % 20 + 1);
+            fields.join("\t")
+        } else {
+            line.to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    std::fs::write(path, tampered).unwrap();
+    let verified = parse_analyze_evaluate(&format!("verify-rolls {:?}", path), &mut env).unwrap();
+    assert_eq!(verified.try_to_num().unwrap().into_owned(), 0);
+
+    // a session that has not been revealed can not be checked
+    std::fs::write(path, &log).unwrap();
+    parse_analyze_evaluate(&format!("commit-rolls {:?}", path), &mut env).unwrap();
+    parse_analyze_evaluate("roll d20", &mut env).unwrap();
+    let verified = parse_analyze_evaluate(&format!("verify-rolls {:?}", path), &mut env).unwrap();
+    assert_eq!(verified.try_to_num().unwrap().into_owned(), 0);
+    let _ = std::fs::remove_file(path);
+}
+#[test]
+fn test_operators() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let num = |line: &str, env: &mut Env| parse_analyze_evaluate(line, env).unwrap().try_to_num().unwrap().into_owned();
+    assert_eq!(num("-2", &mut env), -2);
+    assert_eq!(num("3 - -2", &mut env), 5);
+    assert_eq!(num("--3", &mut env), 3);
+    assert_eq!(num("-2^2", &mut env), -4);
+    assert_eq!(num("2^3^2", &mut env), 512);
+    assert_eq!(num("1 + 2 * 3 ^ 2", &mut env), 19);
+    assert_eq!(num("7 / 2", &mut env), 3);
+    assert_eq!(num("-7 / 2", &mut env), -3);
+    assert_eq!(num("-7 // 2", &mut env), -4);
+    assert_eq!(num("7 // 2", &mut env), 3);
+    assert_eq!(num("7 /^ 2", &mut env), 4);
+    assert_eq!(num("-7 /^ 2", &mut env), -3);
+    assert_eq!(num("7 /^ -2", &mut env), -3);
+    assert_eq!(num("-7 % 3", &mut env), 2);
+    assert_eq!(num("10 - 2 % 3 * 2", &mut env), 6);
+
+    let expr = parse_analyze_evaluate("d6 - -1", &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), distr::Distr::unif(6).map_op(|x| x + 1));
+    let expr = parse_analyze_evaluate("-d4 given >= -2", &mut env).unwrap();
+    assert_eq!(expr.try_to_distr().unwrap().iter().cloned().collect::<Vec<distr::KeyType>>(), vec![-2, -1]);
+    // half damage, rounding down
+    let expr = parse_analyze_evaluate("2d6 // 2", &mut env).unwrap();
+    assert!((expr.try_to_distr().unwrap().prob(1) - 3.0 / 36.0).abs() < 1e-9);
+
+    assert!(parse_analyze_evaluate("d6 / (d2 - 1)", &mut env).is_err());
+    assert!(parse_analyze_evaluate("d6 % 0", &mut env).is_err());
+    assert!(parse_analyze_evaluate("2 ^ -1", &mut env).is_err());
+    // the smallest number has no positive counterpart
+    assert!(parse_analyze_evaluate("(-2147483647 - 1) / -1", &mut env).is_err());
+    assert!(parse_analyze_evaluate("(-2147483647 - 1) // -1", &mut env).is_err());
+    assert!(parse_analyze_evaluate("(-2147483647 - 1) /^ -1", &mut env).is_err());
+    assert!(parse_analyze_evaluate("(-2147483647 - 1) % -1", &mut env).is_err());
+    assert!(parse_analyze_evaluate("-(-2147483647 - 1)", &mut env).is_err());
+    assert_eq!(num("(-2147483647 - 1) // 1", &mut env), -2147483648);
+    assert!(parse_analyze_evaluate("10 ^ 10", &mut env).is_err());
+    // sums and products that do not fit are errors too, rather than wrapping around
+    for line in ["2147483647 + 1", "-2147483647 - 2", "2147483647 * 2", "(-2147483647 - 1) * -1", "2147483647 + d6"] {
+        let err = parse_analyze_evaluate(line, &mut env).unwrap_err();
+        assert!(err.reason.contains("is too large"), "{}: {}", line, err.reason);
+    }
+    assert_eq!(num("2147483646 + 1", &mut env), 2147483647);
+}
+#[test]
+fn test_split_statements() {
+    let src = "a = 2d6; b = a +\n  3\n\n# a comment; with a semicolon\nc = [1,\n 2] # trailing\nd = \"x;\ny\"";
+    let statements = script::split_statements(src)
+        .into_iter()
+        .map(|statement| statement.src)
+        .collect::<Vec<&str>>();
+    assert_eq!(statements, vec!["a = 2d6", "b = a +\n  3", "c = [1,\n 2]", "d = \"x;\ny\""]);
+    assert_eq!(script::split_statements(src)[2].offset, src.find("c = ").unwrap());
+    assert!(script::is_incomplete("x = (d6 +"));
+    assert!(script::is_incomplete("x = d6 given"));
+    assert!(!script::is_incomplete("x = d6; y = x"));
+    assert_eq!(script::line_col("ab\ncd", 4), (2, 2));
+}
+#[test]
+fn test_run_script() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let dir = std::env::temp_dir();
+    let path = dir.join("dnd-calculator-test-party.dnd");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "# the fighter\nstr = 4; prof = 2\nattack = d20 + str +\n    prof\n\ndamage = (2d6 +\n  str)\ndamage\n").unwrap();
+    let last = parse_analyze_evaluate(&format!("run {}", path), &mut env).unwrap();
+    assert_eq!(*last.try_to_distr().unwrap(), distr::Distr::stacked_unifs(2, 6).map_op(|x| x + 4));
+    let attack = parse_analyze_evaluate("attack", &mut env).unwrap();
+    assert_eq!(*attack.try_to_distr().unwrap(), distr::Distr::unif(20).map_op(|x| x + 6));
+    // a name that only starts with run is not a script to run
+    parse_analyze_evaluate("runes = 3", &mut env).unwrap();
+    let runes = parse_analyze_evaluate("runes", &mut env).unwrap();
+    assert_eq!(runes.try_to_num().unwrap().into_owned(), 3);
+
+    let broken = dir.join("dnd-calculator-test-broken.dnd");
+    let broken = broken.to_str().unwrap();
+    std::fs::write(broken, "x = 1\ny = x +\n  (2 * * 3)\n").unwrap();
+    let err = parse_analyze_evaluate(&format!("run {:?}", broken), &mut env).unwrap_err();
+    assert!(err.reason.contains(&format!("{}:3:8:", broken)), "{}", err.reason);
+    // other errors do not know their column, so they only give the line the statement starts on
+    std::fs::write(broken, "x = 1\n\n  nope 3\n").unwrap();
+    let err = parse_analyze_evaluate(&format!("run {:?}", broken), &mut env).unwrap_err();
+    assert!(err.reason.contains(&format!("{}:3: statement failed", broken)), "{}", err.reason);
+    std::fs::write(broken, "x = 1\ny = x +\n  nope\n").unwrap();
+    let err = parse_analyze_evaluate(&format!("run {:?}", broken), &mut env).unwrap_err();
+    assert!(err.reason.contains(&format!("{}:2: statement failed", broken)), "{}", err.reason);
+    // a script that ends part way through an expression fails to parse
+    std::fs::write(broken, "x = 1\ny = x +\n").unwrap();
+    let err = parse_analyze_evaluate(&format!("run {:?}", broken), &mut env).unwrap_err();
+    assert!(err.reason.contains(&format!("{}:2:", broken)), "{}", err.reason);
+    assert!(parse_analyze_evaluate("1 +", &mut env).is_err());
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(broken);
+}
+
+#[test]
+fn test_import() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let dir = std::env::temp_dir().join("dnd-calculator-test-import");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("helpers.dnd"), "bonus = 2\nadd-bonus = \\x -> x + bonus\n").unwrap();
+    std::fs::write(dir.join("monsters.dnd"), "import \"helpers.dnd\" as h\ngoblin = d6 + h.bonus\nhidden = 5\nhit = \\x -> h.add-bonus x + hidden\n").unwrap();
+    let monsters = dir.join("monsters.dnd");
+    parse_analyze_evaluate(&format!("import {:?} as m", monsters.to_str().unwrap()), &mut env).unwrap();
+    let goblin = parse_analyze_evaluate("m.goblin", &mut env).unwrap();
+    assert_eq!(*goblin.try_to_distr().unwrap(), distr::Distr::unif(6).map_op(|x| x + 2));
+    let bonus = parse_analyze_evaluate("m.h.bonus * 2", &mut env).unwrap();
+    assert_eq!(bonus.try_to_num().unwrap().into_owned(), 4);
+    // the imported bindings stay in their namespace
+    assert!(parse_analyze_evaluate("hidden", &mut env).is_err());
+    assert!(parse_analyze_evaluate("m.nothing", &mut env).is_err());
+    // and an imported function uses them, whatever the importer binds
+    parse_analyze_evaluate("bonus = 100", &mut env).unwrap();
+    parse_analyze_evaluate("hidden = 100", &mut env).unwrap();
+    let bonus = parse_analyze_evaluate("m.h.add-bonus 3", &mut env).unwrap();
+    assert_eq!(bonus.try_to_num().unwrap().into_owned(), 5);
+    let hit = parse_analyze_evaluate("m.hit 3", &mut env).unwrap();
+    assert_eq!(hit.try_to_num().unwrap().into_owned(), 10);
+    // without `as`, the namespace is named after the file
+    parse_analyze_evaluate(&format!("import {:?}", monsters.to_str().unwrap()), &mut env).unwrap();
+    let hidden = parse_analyze_evaluate("monsters.hidden", &mut env).unwrap();
+    assert_eq!(hidden.try_to_num().unwrap().into_owned(), 5);
+
+    std::fs::write(dir.join("a.dnd"), "import \"b.dnd\"\n").unwrap();
+    std::fs::write(dir.join("b.dnd"), "import \"a.dnd\"\n").unwrap();
+    let err = parse_analyze_evaluate(&format!("import {:?}", dir.join("a.dnd").to_str().unwrap()), &mut env).unwrap_err();
+    assert!(err.reason.contains("cyclic import"), "{}", err.reason);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_let() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let expr = parse_analyze_evaluate("let x = 3 in x * x + 1", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 10);
+    let expr = parse_analyze_evaluate("let x = d20 + 5 in x", &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), distr::Distr::unif(20).map_op(|x| x + 5));
+    // an inner let shadows an outer one, and a let shadows a global, without overwriting it
+    parse_analyze_evaluate("x = 100", &mut env).unwrap();
+    let expr = parse_analyze_evaluate("let x = 1 in (let x = x + 1 in x * 10) + x", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 21);
+    let expr = parse_analyze_evaluate("x", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 100);
+    // the binding is gone after the let, even when its body failed
+    assert!(parse_analyze_evaluate("let y = 1 in y / 0", &mut env).is_err());
+    assert!(parse_analyze_evaluate("y", &mut env).is_err());
+    // functions capture the lets around them
+    let expr = parse_analyze_evaluate("(let n = 2 in \\y -> y * n) 5", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 10);
+    let expr = parse_analyze_evaluate("let bonus = 3 in map-outcomes (\\r -> r + bonus) d4", &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), distr::Distr::unif(4).map_op(|x| x + 3));
+    // a function body does not see the lets of whoever calls it
+    parse_analyze_evaluate("f = \\a -> a + x", &mut env).unwrap();
+    let expr = parse_analyze_evaluate("let x = 1 in f 1", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 101);
+    // the type checker sees the let too
+    let err = parse_analyze_evaluate("let g = 5 in g 1", &mut env).unwrap_err();
+    assert!(err.reason.contains("type checker failed"), "{}", err.reason);
+}
+
+#[test]
+fn test_idents_and_strings() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    // an identifier with no binding is an error rather than a value, even where types are not checked
+    let err = parse_analyze_evaluate("goblin", &mut env).unwrap_err();
+    assert!(err.reason.contains("\"goblin\" has no binding"), "{}", err.reason);
+    assert!(Symbol::Ident("goblin".to_string()).eval(&mut env).is_err());
+    // a string literal is a value of its own type
+    let expr = parse_analyze_evaluate("\"fire\"", &mut env).unwrap();
+    assert_eq!(expr.try_to_str().unwrap(), "fire");
+    assert_eq!(expr.type_check(&mut env).unwrap(), Type::Str);
+    // paths must be strings, not identifiers
+    parse_analyze_evaluate("chart = 5", &mut env).unwrap();
+    let err = parse_analyze_evaluate("export-svg chart \"title\" d6", &mut env).unwrap_err();
+    assert!(err.reason.contains("type checker failed"), "{}", err.reason);
+    // strings label the distributions in a chart
+    let path = std::env::temp_dir().join("dnd-calculator-test-labels.svg");
+    let line = format!("export-svg {:?} \"damage\" [[\"fire\", 2d6], [\"cold\", 3d4]]", path.to_str().unwrap());
+    parse_analyze_evaluate(&line, &mut env).unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(contents.contains("fire") && contents.contains("cold"));
+}
+
+#[test]
+fn test_type_annotations() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    parse_analyze_evaluate("xs: Seq<Num> = [1, 2, 3]", &mut env).unwrap();
+    assert_eq!(*env.lookup_var("xs").unwrap().1, Type::Seq(Box::new(Type::Num)));
+    parse_analyze_evaluate("f: Fn(Distr) -> Distr = \\x -> x + 1", &mut env).unwrap();
+    assert_eq!(*env.lookup_var("f").unwrap().1, fn_type!(Type::Distr, -> Type::Distr).into());
+    let expr = parse_analyze_evaluate("f d4", &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), distr::Distr::unif(4).map_op(|x| x + 1));
+    // nested sequences and functions, and functions returning functions
+    parse_analyze_evaluate("grid: Seq<Seq<Distr>> = [[d4, d6], [d8]]", &mut env).unwrap();
+    parse_analyze_evaluate("g: Fn(Num) -> Fn(Num) -> Distr = \\a -> \\b -> a * b", &mut env).unwrap();
+    assert_eq!(
+        env.lookup_var("g").unwrap().1.to_string(),
+        "Fn(Num) -> Fn(Num) -> Distr",
+    );
+    // lambda parameters and lets can be annotated too
+    let expr = parse_analyze_evaluate("let n: Num = 2 in (\\s: Seq<Num> -> s) [n]", &mut env).unwrap();
+    assert_eq!(expr.try_to_seq().unwrap().len(), 1);
+    let err = parse_analyze_evaluate("(\\s: Seq<Num> -> s) 3", &mut env).unwrap_err();
+    assert!(err.reason.contains("type checker failed"), "{}", err.reason);
+    let err = parse_analyze_evaluate("n: Seq<Num> = d6", &mut env).unwrap_err();
+    assert!(err.reason.contains("annotated with type Seq<Num>"), "{}", err.reason);
+    // a sequence mixing types is only checked once its items are known
+    let err = parse_analyze_evaluate("ys: Seq<Num> = [1, \"x\"]", &mut env).unwrap_err();
+    assert!(err.reason.contains("annotated with type Num, but found type Str"), "{}", err.reason);
+    assert!(env.lookup_var("ys").is_none());
+    assert!(parse_analyze_evaluate("let ys: Seq<Seq<Num>> = [[1], [\"x\"]] in ys", &mut env).is_err());
+    let err = parse_analyze_evaluate("n: Bogus = 1", &mut env).unwrap_err();
+    assert!(err.reason.contains("invalid type: Bogus"), "{}", err.reason);
+}
+
+#[test]
+fn test_generic_builtins() {
+    let mut env = Env::new();
+    env.import_arithmetic().import_dice();
+    let type_of = |src: &str, env: &mut Env| parse::parse_line(src, env).unwrap().type_check(env).map(|type_| type_.to_string());
+    assert_eq!(type_of("map", &mut env).unwrap(), "Fn(Seq<a>, Fn(a) -> b) -> Seq<b>");
+    // the type variables are worked out from the arguments, so the result is typed precisely
+    assert_eq!(type_of("map [1, 2, 3] (\\x -> x + d4)", &mut env).unwrap(), "Seq<Distr>");
+    assert_eq!(type_of("map [d4, d6] table", &mut env).unwrap(), "Seq<Nil>");
+    assert_eq!(type_of("fold [d4, d6] 0 add", &mut env).unwrap(), "Distr");
+    assert_eq!(type_of("fold [d4, d6] 0 (\\acc -> \\x -> acc + x)", &mut env).unwrap(), "Distr");
+    assert_eq!(type_of("map [[1], [2, 3]] (\\xs -> fold xs 0 add)", &mut env).unwrap(), "Seq<Distr>");
+    // and mistakes are caught before evaluation rather than lost to Any
+    assert!(type_of("map [d4, d6] range-to", &mut env).is_err());
+    assert!(type_of("fold [d4] 0 table", &mut env).is_err());
+    assert!(type_of("table (map [1, 2] (\\x -> x))", &mut env).is_err());
+    // the errors name type variables as they were written, not as they were renamed while checking
+    let expected = [
+        ("map [d4, d6] range-to", "expected type Fn(Distr) -> b, found type Fn(Num, Num) -> Seq<Num>"),
+        ("bad: Fn(Num) -> a = map [1]", "annotated with type Fn(Num) -> a, but found type Fn(Fn(Num) -> b) -> Seq<b>"),
+        // and are kept apart from the annotation's own variables
+        ("bad: Fn(b) -> b = map [1]", "annotated with type Fn(b) -> b, but found type Fn(Fn(Num) -> a) -> Seq<a>"),
+    ];
+    for (src, message) in expected.iter() {
+        let err = parse_analyze_evaluate(src, &mut env).unwrap_err();
+        assert!(err.reason.contains(message), "{}: {}", src, err.reason);
+    }
+
+    let expr = parse_analyze_evaluate("map [1, 2, 3] (\\x -> x * 2)", &mut env).unwrap();
+    let doubled = expr.try_to_seq().unwrap().iter().map(|x| x.try_to_num().unwrap().into_owned()).collect::<Vec<_>>();
+    assert_eq!(doubled, vec![2, 4, 6]);
+    let expr = parse_analyze_evaluate("fold [d4, d4] 0 (\\acc -> \\x -> acc + x)", &mut env).unwrap();
+    assert_eq!(*expr.try_to_distr().unwrap(), distr::Distr::stacked_unifs(2, 4));
+    // a generic annotation accepts any function of the right shape
+    parse_analyze_evaluate("twice: Fn(Fn(a) -> a, a) -> a = \\f -> \\x -> f (f x)", &mut env).unwrap();
+    let expr = parse_analyze_evaluate("twice (\\x -> x + 1) 5", &mut env).unwrap();
+    assert_eq!(expr.try_to_num().unwrap().into_owned(), 7);
+    parse_analyze_evaluate("id: Fn(a) -> a = \\x -> x", &mut env).unwrap();
+    parse_analyze_evaluate("each: Fn(Seq<a>, Fn(a) -> b) -> Seq<b> = map", &mut env).unwrap();
+    // but the variables of an annotation stand for any type at all, so they can not be narrowed or mixed up
+    for src in ["bad: Fn(a) -> b = \\x -> x", "bad: Fn(a) -> a = \\x -> x + 1", "bad: Fn(a) -> a = \\x: Num -> x", "bad: Fn(Num) -> a = \\x -> x"].iter() {
+        let err = parse_analyze_evaluate(src, &mut env).unwrap_err();
+        assert!(err.reason.contains("bad is annotated with type"), "{}: {}", src, err.reason);
+    }
+}
@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use crate::closures::FnType;
 
+/// what each type variable stands for, as worked out so far while checking one function application
+pub type Bindings = HashMap<String, Type>;
+
 impl std::convert::From<FnType> for Type {
     fn from(fn_type: FnType) -> Self {
         Type::Fn(fn_type)
@@ -10,9 +15,13 @@ pub enum Type {
     Nil,
     Any,
     Num,
+    Prob,
+    Str,
     Distr,
     Seq(Box<Type>),
     Fn(FnType),
+    /// a type variable, i.e. the `a` in `Fn(Seq<a>, Fn(a) -> b) -> Seq<b>`
+    Var(String),
 }
 
 impl std::fmt::Display for Type {
@@ -21,9 +30,12 @@ impl std::fmt::Display for Type {
             Type::Nil => write!(f, "Nil"),
             Type::Any => write!(f, "Any"),
             Type::Num => write!(f, "Num"),
+            Type::Prob => write!(f, "Prob"),
+            Type::Str => write!(f, "Str"),
             Type::Distr => write!(f, "Distr"),
             Type::Seq(ref inner_type) => write!(f, "Seq<{}>", inner_type),
             Type::Fn(ref fn_type) => write!(f, "{}", fn_type),
+            Type::Var(ref name) => write!(f, "{}", name),
         }
     }
 }
@@ -32,6 +44,8 @@ impl Type {
         match s {
             "Nil" => Some(Type::Nil),
             "Num" => Some(Type::Num),
+            "Prob" => Some(Type::Prob),
+            "Str" => Some(Type::Str),
             "Distr" => Some(Type::Distr),
             "Any" => Some(Type::Any),
             _ => None
@@ -47,16 +61,215 @@ impl Type {
     pub fn is_any(&self) -> bool { *self == Type::Any }
     pub fn coercible_to(&self, type_: &Type) -> bool {
         // Type::Any can be coerced into anything and anything can be coerced into Type::Any
-        if *self == Type::Any || *type_ == Type::Any {
+        // outside of unification, a type variable could be anything too
+        if *self == Type::Any || *type_ == Type::Any || matches!(self, Type::Var(_)) || matches!(type_, Type::Var(_)) {
             return true;
         }
         // numbers can be coerced into distributions
         if *self == Type::Num && *type_ == Type::Distr {
             return true;
         }
+        // sequences are coerced based on their items
+        if let (Type::Seq(found), Type::Seq(expected)) = (self, type_) {
+            return found.coercible_to(expected);
+        }
         // functions are coerced based on their output
+        // the expected inputs must also be acceptable to the function we have
+        if let (Type::Fn(found), Type::Fn(expected)) = (self, type_) {
+            return found.in_types.len() == expected.in_types.len()
+                && expected.in_types.iter().zip(found.in_types.iter()).all(|(e, f)| e.coercible_to(f))
+                && found.out_type.coercible_to(&expected.out_type);
+        }
 
         // otherwise they must match exactly
         *self == *type_
     }
+    /// the most specific type that both types can be coerced to, i.e. `Distr` for `Num` and `Distr`
+    pub fn join(&self, other: &Type) -> Type {
+        if self.is_any() || other.is_any() {
+            Type::Any
+        } else if self.coercible_to(other) {
+            other.clone()
+        } else if other.coercible_to(self) {
+            self.clone()
+        } else {
+            Type::Any
+        }
+    }
+    /// this type with every bound type variable replaced by what it stands for
+    pub fn resolve(&self, bindings: &Bindings) -> Type {
+        match *self {
+            Type::Var(ref name) => bindings.get(name).map_or_else(|| self.clone(), |bound| bound.resolve(bindings)),
+            Type::Seq(ref item) => Type::Seq(Box::new(item.resolve(bindings))),
+            Type::Fn(ref fn_type) => FnType {
+                in_types: fn_type.in_types.iter().map(|type_| type_.resolve(bindings)).collect(),
+                out_type: Box::new(fn_type.out_type.resolve(bindings)),
+            }.into(),
+            _ => self.clone(),
+        }
+    }
+    fn shallow_resolve(&self, bindings: &Bindings) -> Type {
+        match *self {
+            Type::Var(ref name) => bindings.get(name).map_or_else(|| self.clone(), |bound| bound.shallow_resolve(bindings)),
+            _ => self.clone(),
+        }
+    }
+    /// this type with fresh type variables, so that each use of a generic function is checked on its own
+    pub fn instantiate(&self) -> Type {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        self.rename(&|name: &str| format!("{}'{}", name.split('\'').next().unwrap_or(name), n))
+    }
+    /// this type as it should be shown to the user, see `readable_all`
+    pub fn readable(&self) -> Type {
+        Type::readable_all(&[self]).remove(0)
+    }
+    /// these types with the variables renamed by `instantiate` given back short names, i.e. `b'27` becomes `b`
+    /// the types are renamed together, so a variable they share keeps one name, and variables that would clash get the next free letter
+    pub fn readable_all(types: &[&Type]) -> Vec<Type> {
+        let mut vars = vec![];
+        for type_ in types {
+            type_.collect_vars(&mut vars);
+        }
+        let mut taken = vars.iter().filter(|name| !name.contains('\'')).cloned().collect::<Vec<String>>();
+        let mut renamed = HashMap::new();
+        for name in vars.iter().filter(|name| name.contains('\'')) {
+            if renamed.contains_key(name) {
+                continue;
+            }
+            let base = name.split('\'').next().unwrap_or(name).to_string();
+            let short = std::iter::once(base)
+                .chain((b'a'..=b'z').map(|c| (c as char).to_string()))
+                .chain((1..).map(|i| format!("t{}", i)))
+                .find(|short| !taken.contains(short))
+                .expect("there is always a free name");
+            taken.push(short.clone());
+            renamed.insert(name.clone(), short);
+        }
+        types.iter()
+            .map(|type_| type_.rename(&|name: &str| renamed.get(name).cloned().unwrap_or_else(|| name.to_string())))
+            .collect()
+    }
+    /// the names of this type's variables, in the order they first appear
+    fn collect_vars(&self, vars: &mut Vec<String>) {
+        match *self {
+            Type::Var(ref name) if !vars.contains(name) => vars.push(name.clone()),
+            Type::Seq(ref item) => item.collect_vars(vars),
+            Type::Fn(ref fn_type) => {
+                for type_ in fn_type.in_types.iter().chain(std::iter::once(fn_type.out_type.as_ref())) {
+                    type_.collect_vars(vars);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn rename(&self, f: &dyn Fn(&str) -> String) -> Type {
+        match *self {
+            Type::Var(ref name) => Type::Var(f(name)),
+            Type::Seq(ref item) => Type::Seq(Box::new(item.rename(f))),
+            Type::Fn(ref fn_type) => FnType {
+                in_types: fn_type.in_types.iter().map(|type_| type_.rename(f)).collect(),
+                out_type: Box::new(fn_type.out_type.rename(f)),
+            }.into(),
+            _ => self.clone(),
+        }
+    }
+    fn occurs(&self, name: &str, bindings: &Bindings) -> bool {
+        match self.resolve(bindings) {
+            Type::Var(ref other) => other == name,
+            Type::Seq(ref item) => item.occurs(name, bindings),
+            Type::Fn(ref fn_type) => fn_type.in_types.iter().chain(std::iter::once(fn_type.out_type.as_ref())).any(|type_| type_.occurs(name, bindings)),
+            _ => false,
+        }
+    }
+    /// whether a value of type `found` fits this annotation, whose type variables are rigid: `a` stands for
+    /// whatever type the user of the value picks, so it only fits itself, and not `Any`, another variable or a concrete type
+    /// the variables of `found` are renamed apart first, and can still become whatever the annotation needs
+    pub fn admits(&self, found: &Type) -> bool {
+        self.fits(&found.instantiate(), &mut Bindings::new())
+    }
+    /// as `unify`, but only the variables renamed by `instantiate` are bound, and neither side is widened
+    fn fits(&self, found: &Type, bindings: &mut Bindings) -> bool {
+        let is_flexible = |name: &str| name.contains('\'');
+        let (expected, found) = (self.shallow_resolve(bindings), found.shallow_resolve(bindings));
+        match (&expected, &found) {
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(a), _) if is_flexible(a) && !found.occurs(a, bindings) => {
+                bindings.insert(a.clone(), found);
+                true
+            }
+            (_, Type::Var(b)) if is_flexible(b) && !expected.occurs(b, bindings) => {
+                bindings.insert(b.clone(), expected);
+                true
+            }
+            (Type::Any, _) => true,
+            (_, Type::Any) => !expected.has_rigid_vars(bindings),
+            (Type::Var(_), _) | (_, Type::Var(_)) => false,
+            (Type::Seq(expected_item), Type::Seq(found_item)) => expected_item.fits(found_item, bindings),
+            (Type::Fn(expected_fn), Type::Fn(found_fn)) => {
+                let n = expected_fn.in_types.len().min(found_fn.in_types.len());
+                let (expected_fn, found_fn) = (expected_fn.split_at(n), found_fn.split_at(n));
+                if expected_fn.in_types.len() != found_fn.in_types.len() {
+                    return false;
+                }
+                expected_fn.in_types.iter().zip(found_fn.in_types.iter()).all(|(e, f)| f.fits(e, bindings))
+                    && expected_fn.out_type.fits(&found_fn.out_type, bindings)
+            }
+            _ => found.coercible_to(&expected),
+        }
+    }
+    fn has_rigid_vars(&self, bindings: &Bindings) -> bool {
+        match self.resolve(bindings) {
+            Type::Var(ref name) => !name.contains('\''),
+            Type::Seq(ref item) => item.has_rigid_vars(bindings),
+            Type::Fn(ref fn_type) => fn_type.in_types.iter().chain(std::iter::once(fn_type.out_type.as_ref())).any(|type_| type_.has_rigid_vars(bindings)),
+            _ => false,
+        }
+    }
+    /// whether a value of type `found` can be passed where this type is expected, binding type variables as needed
+    /// a variable already bound to a narrower type is widened, so `Num` then `Distr` makes it a `Distr`
+    pub fn unify(&self, found: &Type, bindings: &mut Bindings) -> bool {
+        if let Type::Var(ref name) = *self {
+            if let Some(bound) = bindings.get(name).cloned() {
+                if bound.unify(found, bindings) {
+                    return true;
+                }
+                let (bound, found) = (bound.resolve(bindings), found.resolve(bindings));
+                if bound.coercible_to(&found) && !found.occurs(name, bindings) {
+                    bindings.insert(name.clone(), found);
+                    return true;
+                }
+                return false;
+            }
+        }
+        // only the outermost variables are resolved here, so that the ones inside can still be widened
+        let (expected, found) = (self.shallow_resolve(bindings), found.shallow_resolve(bindings));
+        match (&expected, &found) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(a), _) if found.occurs(a, bindings) => false,
+            (Type::Var(a), _) => {
+                bindings.insert(a.clone(), found);
+                true
+            }
+            (_, Type::Var(b)) if expected.occurs(b, bindings) => false,
+            (_, Type::Var(b)) => {
+                bindings.insert(b.clone(), expected);
+                true
+            }
+            (Type::Seq(expected_item), Type::Seq(found_item)) => expected_item.unify(found_item, bindings),
+            (Type::Fn(expected_fn), Type::Fn(found_fn)) => {
+                // `Fn(A, B) -> C` and `Fn(A) -> Fn(B) -> C` are the same function, curried differently
+                let n = expected_fn.in_types.len().min(found_fn.in_types.len());
+                let (expected_fn, found_fn) = (expected_fn.split_at(n), found_fn.split_at(n));
+                if expected_fn.in_types.len() != found_fn.in_types.len() {
+                    return false;
+                }
+                // the function we have must accept whatever inputs are expected of it
+                expected_fn.in_types.iter().zip(found_fn.in_types.iter()).all(|(e, f)| f.unify(e, bindings))
+                    && expected_fn.out_type.unify(&found_fn.out_type, bindings)
+            }
+            _ => found.coercible_to(&expected),
+        }
+    }
 }